@@ -0,0 +1,96 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use keycat::analysis::{Analyzer, MetricAmount, MetricData, NstrokeData};
+use keycat::state::LayoutState;
+use keycat::{Corpus, Layout, NgramType, Nstroke, Swap};
+
+const CHARS: &str = "abcdefghijklmnopqrstuvwxyz,./;";
+
+/// Builds a corpus from pseudo-random text, so that most ngrams have
+/// nonzero frequencies.
+fn corpus() -> Corpus {
+    let mut corpus = Corpus::with_char_list(CHARS.chars().map(|c| vec![c]).collect());
+    let chars: Vec<char> = CHARS.chars().collect();
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let text: String = (0..200_000)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            chars[(seed % 30) as usize]
+        })
+        .collect();
+    corpus.add_str(&text);
+    corpus
+}
+
+/// A 30-key analyzer with same-finger bigrams and skipgrams plus a
+/// hand alternation metric on every possible trigram.
+fn analyzer() -> Analyzer {
+    let finger = |p: usize| (p / 3).min(7);
+    let metrics = vec![NgramType::Bigram, NgramType::Skipgram, NgramType::Trigram];
+    let mut strokes = vec![];
+    for a in 0..30 {
+        for b in 0..30 {
+            if a != b && finger(a) == finger(b) {
+                strokes.push(NstrokeData::new(
+                    Nstroke::Bistroke([a, b]),
+                    vec![MetricAmount::new(0, 1.0), MetricAmount::new(1, 1.0)],
+                ));
+            }
+            for c in 0..30 {
+                let hands = [a, b, c].map(|p| p < 15);
+                let alternations = u8::from(hands[0] != hands[1]) + u8::from(hands[1] != hands[2]);
+                strokes.push(NstrokeData::new(
+                    Nstroke::Tristroke([a, b, c]),
+                    vec![MetricAmount::new(2, f32::from(alternations))],
+                ));
+            }
+        }
+    }
+    Analyzer::from(MetricData::from(metrics, strokes, 30), corpus())
+}
+
+fn swap_diff(c: &mut Criterion) {
+    let analyzer = analyzer();
+    let layout: Layout = analyzer
+        .corpus
+        .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+    let swaps: Vec<Swap> = (0..30)
+        .flat_map(|a| (a + 1..30).map(move |b| Swap::new(a, b)))
+        .collect();
+
+    c.bench_function("analyzer swap_diff", |bench| {
+        let mut diffs = vec![0.0; 3];
+        bench.iter(|| {
+            for swap in &swaps {
+                diffs.fill(0.0);
+                analyzer.swap_diff(&mut diffs, &layout, black_box(swap));
+            }
+            black_box(&diffs);
+        });
+    });
+
+    c.bench_function("state swap_diff", |bench| {
+        let mut state = LayoutState::new(&analyzer, layout.clone());
+        bench.iter(|| {
+            for swap in &swaps {
+                black_box(state.swap_diff(&analyzer, black_box(swap)));
+            }
+        });
+    });
+}
+
+fn swap_matrix(c: &mut Criterion) {
+    let analyzer = analyzer();
+    let layout: Layout = analyzer
+        .corpus
+        .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+    let state = LayoutState::new(&analyzer, layout);
+
+    c.bench_function("state swap_matrix", |bench| {
+        bench.iter(|| black_box(state.swap_matrix(&analyzer)));
+    });
+}
+
+criterion_group!(benches, swap_diff, swap_matrix);
+criterion_main!(benches);
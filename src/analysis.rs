@@ -1,4 +1,7 @@
-use crate::{Corpus, Layout, NgramType, Nstroke, Swap};
+use crate::corpus::CorpusIndex;
+use crate::{
+    Corpus, CorpusChar, Error, Layout, LayoutError, NgramType, Nstroke, Permutation, Pos, Swap,
+};
 use std::cmp::Ordering;
 
 #[cfg(feature = "serde")]
@@ -39,6 +42,111 @@ impl NstrokeData {
     }
 }
 
+/// Weights on a character being placed at a position, regardless of
+/// frequency. Used for scoring like keeping shortcut keys in place or
+/// punctuation on one hand.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct AffinityData {
+    pub pos: Pos,
+    #[cfg_attr(feature = "serde", serde(rename = "ch"))]
+    pub char: CorpusChar,
+    /// Added to the metrics when `char` is at `pos`. The `NgramType`
+    /// of the metrics is irrelevant for affinities.
+    #[cfg_attr(feature = "serde", serde(rename = "ams"))]
+    pub amounts: Vec<MetricAmount>,
+}
+
+impl AffinityData {
+    #[must_use]
+    pub fn new(pos: Pos, char: CorpusChar, amounts: Vec<MetricAmount>) -> Self {
+        Self { pos, char, amounts }
+    }
+}
+
+/// How an `AggregateMetric` combines the values of its inputs. An
+/// aggregate of no inputs is always zero.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The population variance of the inputs, e.g. of finger usage.
+    Variance,
+    /// The largest input, e.g. the highest finger load.
+    Max,
+    /// The smallest input.
+    Min,
+    /// The first input's share of the sum of all inputs, e.g. the left
+    /// hand's share of usage with left and right hand usage as inputs.
+    /// Zero when the sum is zero.
+    Share,
+    /// The first input divided by the second, which takes exactly two
+    /// inputs. Zero when the second input is zero.
+    Ratio,
+}
+
+/// A metric that isn't a sum over strokes, but a non-linear function
+/// of other metrics. Its inputs are kept up to date incrementally like
+/// any other metric, and the aggregate is recalculated from them.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct AggregateMetric {
+    pub aggregation: Aggregation,
+    /// The metrics being aggregated. An aggregate may use the stroke
+    /// metrics and any aggregate before it.
+    pub inputs: Vec<MetricIndex>,
+}
+
+impl AggregateMetric {
+    /// Fails if `aggregation` can't take that many inputs: a ratio
+    /// needs exactly two, and every other aggregation at least one.
+    pub fn new(aggregation: Aggregation, inputs: Vec<MetricIndex>) -> Result<Self, Error> {
+        let valid = match aggregation {
+            Aggregation::Ratio => inputs.len() == 2,
+            _ => !inputs.is_empty(),
+        };
+        if !valid {
+            return Err(Error::Arity {
+                aggregation,
+                count: inputs.len(),
+            });
+        }
+        Ok(Self {
+            aggregation,
+            inputs,
+        })
+    }
+    /// Calculates the aggregate, with `stat` returning the value of
+    /// each input.
+    #[allow(clippy::cast_precision_loss)]
+    fn value(&self, stat: impl Fn(MetricIndex) -> f32) -> f32 {
+        if self.inputs.is_empty() {
+            return 0.0;
+        }
+        let inputs = self.inputs.iter().map(|m| stat(*m));
+        match self.aggregation {
+            Aggregation::Variance => {
+                let n = self.inputs.len() as f32;
+                let mean = inputs.clone().sum::<f32>() / n;
+                inputs.map(|x| (x - mean) * (x - mean)).sum::<f32>() / n
+            }
+            Aggregation::Max => inputs.fold(f32::NEG_INFINITY, f32::max),
+            Aggregation::Min => inputs.fold(f32::INFINITY, f32::min),
+            Aggregation::Share => {
+                let sum: f32 = inputs.sum();
+                if sum == 0.0 {
+                    0.0
+                } else {
+                    stat(self.inputs[0]) / sum
+                }
+            }
+            Aggregation::Ratio => match self.inputs[..] {
+                [a, b] if stat(b) != 0.0 => stat(a) / stat(b),
+                _ => 0.0,
+            },
+        }
+    }
+}
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug)]
 /// Structure for storing metric data and performing analysis on layouts.
@@ -50,6 +158,13 @@ pub struct MetricData {
     pub strokes: Vec<NstrokeData>,
     /// Maps a position to all of the strokes that contain it.
     pub position_strokes: Vec<Vec<NstrokeIndex>>,
+    /// Non-linear metrics. Aggregate `i` has the stat index
+    /// `metrics.len() + i`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub aggregates: Vec<AggregateMetric>,
+    /// Position-character affinities.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub affinities: Vec<AffinityData>,
 }
 
 impl MetricData {
@@ -66,20 +181,211 @@ impl MetricData {
         let mut position_strokes: Vec<Vec<NstrokeIndex>> = vec![vec![]; num_positions];
         for (i, stroke) in strokes.iter().map(|s| &s.nstroke).enumerate() {
             for pos in stroke.to_vec() {
-                position_strokes[pos].push(i);
+                // a stroke may repeat a position, but should only be
+                // listed once
+                if position_strokes[pos].last() != Some(&i) {
+                    position_strokes[pos].push(i);
+                }
             }
         }
         Self {
             metrics,
             strokes,
             position_strokes,
+            aggregates: vec![],
+            affinities: vec![],
+        }
+    }
+    /// Like `MetricData::from`, but fails if a stroke has a position
+    /// past `num_positions` or an amount for a metric that isn't in
+    /// `metrics`.
+    pub fn checked_from(
+        metrics: Vec<NgramType>,
+        strokes: Vec<NstrokeData>,
+        num_positions: usize,
+    ) -> Result<Self, Error> {
+        for stroke in &strokes {
+            if let Some(pos) = stroke
+                .nstroke
+                .to_vec()
+                .into_iter()
+                .find(|p| *p >= num_positions)
+            {
+                return Err(Error::PositionOutOfRange {
+                    pos,
+                    len: num_positions,
+                });
+            }
+            check_amounts(&stroke.amounts, metrics.len())?;
         }
+        Ok(Self::from(metrics, strokes, num_positions))
+    }
+    /// Adds position-character affinities.
+    #[must_use]
+    pub fn with_affinities(mut self, affinities: Vec<AffinityData>) -> Self {
+        self.affinities = affinities;
+        self
+    }
+    /// Like `MetricData::with_affinities`, but fails if an affinity
+    /// has a position past the end of the data or an amount for a
+    /// metric that isn't a stroke metric.
+    pub fn try_with_affinities(self, affinities: Vec<AffinityData>) -> Result<Self, Error> {
+        let data = self.with_affinities(affinities);
+        data.check_affinities()?;
+        Ok(data)
+    }
+    /// Adds non-linear metrics, placed after the stroke metrics in
+    /// the stats.
+    #[must_use]
+    pub fn with_aggregates(mut self, aggregates: Vec<AggregateMetric>) -> Self {
+        self.aggregates = aggregates;
+        self
+    }
+    /// Like `MetricData::with_aggregates`, but fails if an aggregate
+    /// has the wrong number of inputs, or an input that isn't a stroke
+    /// metric or an aggregate before it.
+    pub fn try_with_aggregates(self, aggregates: Vec<AggregateMetric>) -> Result<Self, Error> {
+        let data = self.with_aggregates(aggregates);
+        data.check_aggregates()?;
+        Ok(data)
+    }
+    /// Checks everything that `MetricData::checked_from`,
+    /// `MetricData::try_with_affinities` and
+    /// `MetricData::try_with_aggregates` do, such as for data that was
+    /// deserialized.
+    pub fn check(&self) -> Result<(), Error> {
+        let len = self.position_strokes.len();
+        for stroke in &self.strokes {
+            if let Some(pos) = stroke.nstroke.to_vec().into_iter().find(|p| *p >= len) {
+                return Err(Error::PositionOutOfRange { pos, len });
+            }
+            check_amounts(&stroke.amounts, self.metrics.len())?;
+        }
+        self.check_affinities()?;
+        self.check_aggregates()
+    }
+    fn check_affinities(&self) -> Result<(), Error> {
+        let len = self.position_strokes.len();
+        for affinity in &self.affinities {
+            if affinity.pos >= len {
+                return Err(Error::PositionOutOfRange {
+                    pos: affinity.pos,
+                    len,
+                });
+            }
+            check_amounts(&affinity.amounts, self.metrics.len())?;
+        }
+        Ok(())
+    }
+    fn check_aggregates(&self) -> Result<(), Error> {
+        for (i, aggregate) in self.aggregates.iter().enumerate() {
+            AggregateMetric::new(aggregate.aggregation, aggregate.inputs.clone())?;
+            // later aggregates aren't updated yet when this one is
+            let len = self.metrics.len() + i;
+            if let Some(metric) = aggregate.inputs.iter().find(|m| **m >= len) {
+                return Err(Error::MetricOutOfRange {
+                    metric: *metric,
+                    len,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// The number of stats produced by analysis: the stroke metrics
+    /// followed by the aggregates.
+    #[must_use]
+    pub fn stat_count(&self) -> usize {
+        self.metrics.len() + self.aggregates.len()
     }
 }
 
+/// Fails if any of `amounts` is for a metric past `len`.
+fn check_amounts(amounts: &[MetricAmount], len: usize) -> Result<(), Error> {
+    match amounts.iter().find(|a| a.metric >= len) {
+        Some(a) => Err(Error::MetricOutOfRange {
+            metric: a.metric,
+            len,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// A stroke preprocessed for fast diffing. Its characters are
+/// turned into an index into one of the `Corpus` frequency tables
+/// using `strides`, so swapping two characters only needs to adjust
+/// the index. Kept small so that many fit in cache.
+struct StrokeKernel {
+    positions: [u32; 3],
+    strides: [u32; 3],
+    /// Start of the stroke's amounts in `Analyzer::kernel_amounts`.
+    /// The first `base_len` apply to the base frequency, the next
+    /// `skip_len` to the skipgram frequency.
+    amounts: u32,
+    base_len: u8,
+    skip_len: u8,
+    len: u8,
+    ngram: NgramType,
+}
+
+impl StrokeKernel {
+    #[inline]
+    fn index(&self, l: &Layout) -> CorpusIndex {
+        (0..self.len as usize)
+            .map(|i| l.0[self.positions[i] as usize] * self.strides[i] as usize)
+            .sum()
+    }
+    #[inline]
+    #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+    fn swapped_index(
+        &self,
+        idx: CorpusIndex,
+        swap: &Swap,
+        c_a: CorpusChar,
+        c_b: CorpusChar,
+    ) -> CorpusIndex {
+        let delta = c_b as isize - c_a as isize;
+        let mut idx = idx as isize;
+        for i in 0..self.len as usize {
+            let pos = self.positions[i] as usize;
+            let stride = self.strides[i] as isize;
+            if pos == swap.a {
+                idx += delta * stride;
+            } else if pos == swap.b {
+                idx -= delta * stride;
+            }
+        }
+        idx as CorpusIndex
+    }
+    #[inline]
+    fn contains(&self, pos: Pos) -> bool {
+        self.positions[..self.len as usize]
+            .iter()
+            .any(|p| *p as usize == pos)
+    }
+}
+
+/// The table index and frequencies of a stroke on the current layout.
+#[derive(Clone, Copy, Default)]
+struct CachedStroke {
+    index: u32,
+    freqs: [u32; 2],
+}
+
+/// The current state of every stroke on a layout, maintained by
+/// `LayoutState`.
+#[derive(Clone)]
+pub(crate) struct StrokeCache(Vec<CachedStroke>);
+
+/// Analyzes layouts using a `MetricData` and `Corpus`. The strokes
+/// are preprocessed on construction, so the metric data and the
+/// corpus character list shouldn't be changed afterwards.
 pub struct Analyzer {
     pub data: MetricData,
     pub corpus: Corpus,
+    kernels: Vec<StrokeKernel>,
+    kernel_amounts: Vec<(MetricIndex, f32)>,
+    /// Maps a position to all of the affinities on it.
+    pub(crate) position_affinities: Vec<Vec<usize>>,
 }
 
 #[allow(clippy::cast_precision_loss)]
@@ -94,25 +400,130 @@ impl Analyzer {
     }
 
     #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
     pub fn from(data: MetricData, corpus: Corpus) -> Self {
-        Self { data, corpus }
+        let len = corpus.char_list.len() as u32;
+        let mut kernel_amounts = vec![];
+        let kernels = data
+            .strokes
+            .iter()
+            .map(|stroke| {
+                let (positions, strides, len, ngram) = match stroke.nstroke {
+                    Nstroke::Monostroke(a) => ([a, a, a], [1, 0, 0], 1, NgramType::Monogram),
+                    Nstroke::Bistroke([a, b]) => ([a, b, b], [len, 1, 0], 2, NgramType::Bigram),
+                    Nstroke::Tristroke([a, b, c]) => {
+                        ([a, b, c], [len * len, len, 1], 3, NgramType::Trigram)
+                    }
+                };
+                let is_skip =
+                    |a: &&MetricAmount| matches!(data.metrics[a.metric], NgramType::Skipgram);
+                let start = kernel_amounts.len();
+                kernel_amounts.extend(
+                    stroke
+                        .amounts
+                        .iter()
+                        .filter(|a| !is_skip(a))
+                        .map(|a| (a.metric, a.amount)),
+                );
+                let base_len = kernel_amounts.len() - start;
+                // skipgram frequencies only exist for bistrokes
+                if ngram == NgramType::Bigram {
+                    kernel_amounts.extend(
+                        stroke
+                            .amounts
+                            .iter()
+                            .filter(is_skip)
+                            .map(|a| (a.metric, a.amount)),
+                    );
+                }
+                let skip_len = kernel_amounts.len() - start - base_len;
+                StrokeKernel {
+                    positions: positions.map(|p| p as u32),
+                    strides,
+                    amounts: start as u32,
+                    base_len: base_len as u8,
+                    skip_len: skip_len as u8,
+                    len,
+                    ngram,
+                }
+            })
+            .collect();
+        let mut position_affinities = vec![vec![]; data.position_strokes.len()];
+        for (i, affinity) in data.affinities.iter().enumerate() {
+            position_affinities[affinity.pos].push(i);
+        }
+        Self {
+            data,
+            corpus,
+            kernels,
+            kernel_amounts,
+            position_affinities,
+        }
+    }
+    /// Like `Analyzer::from`, but fails if `data` doesn't pass
+    /// `MetricData::check`.
+    pub fn checked_from(data: MetricData, corpus: Corpus) -> Result<Self, Error> {
+        data.check()?;
+        Ok(Self::from(data, corpus))
+    }
+    /// Checks that `l` can be analyzed: it has a character for each
+    /// position, every character is in the `Corpus`, and no character
+    /// other than the null character appears twice.
+    pub fn validate(&self, l: &Layout) -> Result<(), LayoutError> {
+        let expected = self.data.position_strokes.len();
+        if l.0.len() != expected {
+            return Err(LayoutError::Length {
+                expected,
+                found: l.0.len(),
+            });
+        }
+        let mut first_pos = vec![None; self.corpus.char_list.len()];
+        for (pos, &char) in l.0.iter().enumerate() {
+            let Some(first) = first_pos.get_mut(char) else {
+                return Err(LayoutError::OutOfRange { pos, char });
+            };
+            match first {
+                Some(first) if char != 0 => {
+                    return Err(LayoutError::Duplicate {
+                        char,
+                        first: *first,
+                        second: pos,
+                    })
+                }
+                _ => *first = Some(pos),
+            }
+        }
+        Ok(())
+    }
+    /// Creates a layout from `chars`, checking it with
+    /// `Analyzer::validate`.
+    pub fn layout(&self, chars: Vec<CorpusChar>) -> Result<Layout, LayoutError> {
+        let l = Layout(chars);
+        self.validate(&l)?;
+        Ok(l)
     }
     #[must_use]
     /// Calculates base statistics for a layout.
     pub fn calc_stats(&self, l: &Layout) -> Vec<f32> {
-        let mut stats: Vec<f32> = vec![0.0; self.data.metrics.len()];
+        let mut stats: Vec<f32> = vec![0.0; self.data.stat_count()];
         self.recalc_stats(&mut stats, l);
         stats
     }
 
-    pub fn recalc_stats(&self, stats: &mut [f32], l: &Layout) {
-        for stroke in &self.data.strokes {
-            let ns = &stroke.nstroke;
-            let basefreq = l.frequency(&self.corpus, ns, None);
-            let skipfreq = match ns {
+    /// Returns the base and skipgram frequencies of a stroke.
+    fn stroke_freqs(&self, l: &Layout, ns: &Nstroke) -> [u32; 2] {
+        [
+            l.frequency(&self.corpus, ns, None),
+            match ns {
                 Nstroke::Bistroke(_) => l.frequency(&self.corpus, ns, Some(NgramType::Skipgram)),
                 _ => 0,
-            };
+            },
+        ]
+    }
+
+    pub fn recalc_stats(&self, stats: &mut [f32], l: &Layout) {
+        for stroke in &self.data.strokes {
+            let [basefreq, skipfreq] = self.stroke_freqs(l, &stroke.nstroke);
 
             for amount in &stroke.amounts {
                 let freq = if let NgramType::Skipgram = &self.data.metrics[amount.metric] {
@@ -123,120 +534,407 @@ impl Analyzer {
                 stats[amount.metric] += freq as f32 * amount.amount;
             }
         }
+        for affinity in &self.data.affinities {
+            if l.0[affinity.pos] == affinity.char {
+                for amount in &affinity.amounts {
+                    stats[amount.metric] += amount.amount;
+                }
+            }
+        }
+        if stats.len() == self.data.stat_count() {
+            self.update_aggregates(stats);
+        }
+    }
+
+    /// Recalculates the aggregate metrics in `stats` from their
+    /// inputs.
+    pub fn update_aggregates(&self, stats: &mut [f32]) {
+        let first = self.data.metrics.len();
+        for (i, aggregate) in self.data.aggregates.iter().enumerate() {
+            stats[first + i] = aggregate.value(|m| stats[m]);
+        }
+    }
+
+    /// Fills in the diffs of the aggregate metrics, given the current
+    /// `stats` and `diffs` that already hold the stroke metric diffs.
+    /// The diff methods of `Analyzer` only calculate stroke metrics,
+    /// since aggregates depend on the current stats.
+    pub fn aggregate_diffs(&self, stats: &[f32], diffs: &mut [f32]) {
+        let first = self.data.metrics.len();
+        for (i, aggregate) in self.data.aggregates.iter().enumerate() {
+            let new = aggregate.value(|m| stats[m] + diffs[m]);
+            diffs[first + i] = new - stats[first + i];
+        }
     }
 
     /// Calculates the difference in stats between two layout states,
-    /// the original and the state after the `Swap` is applied.
+    /// the original and the state after the `Swap` is applied. The
+    /// aggregate metrics are left untouched, see
+    /// `Analyzer::aggregate_diffs`.
     pub fn swap_diff(&self, diffs: &mut [f32], l: &Layout, swap: &Swap) {
-        let corpus = &self.corpus;
-        let c_a = l.0[swap.a];
-        let c_b = l.0[swap.b];
-        let it1 = &mut self.data.position_strokes[swap.a].iter();
-        let it2 = &mut self.data.position_strokes[swap.b].iter();
-        let mut stroke_a = None;
-        let mut stroke_b = None;
-        loop {
-            match (stroke_a, stroke_b) {
-                (None, None) => {
-                    stroke_a = it1.next();
-                    stroke_b = it2.next();
-                }
-                (Some(_), None) => {
-                    stroke_a = it1.next();
-                }
-                (None, Some(_)) => {
-                    stroke_b = it2.next();
-                }
-                (Some(a), Some(b)) => match a.cmp(b) {
-                    Ordering::Less => {
-                        stroke_a = it1.next();
-                    }
-                    Ordering::Greater => {
-                        stroke_b = it2.next();
-                    }
-                    Ordering::Equal => {
-                        stroke_a = it1.next();
-                        stroke_b = it2.next();
-                    }
-                },
-            };
+        if swap.a == swap.b {
+            return;
+        }
+        let (c_a, c_b) = (l.0[swap.a], l.0[swap.b]);
+        self.for_swap_strokes(swap, |_, kernel| {
+            let old = kernel.index(l);
+            let new = kernel.swapped_index(old, swap, c_a, c_b);
+            self.add_kernel_diff(diffs, kernel, self.kernel_freqs(kernel, old), new);
+        });
+        self.affinity_diff(diffs, swap.a, c_a, c_b);
+        self.affinity_diff(diffs, swap.b, c_b, c_a);
+    }
 
-            let stroke = match (stroke_a, stroke_b) {
-                (None, None) => {
-                    break;
-                }
-                (Some(a), None) => a,
-                (None, Some(b)) => b,
-                (Some(a), Some(b)) => a.min(b),
+    /// Adds the diff of the affinities on `pos` when its character
+    /// changes from `old` to `new`.
+    fn affinity_diff(&self, diffs: &mut [f32], pos: Pos, old: CorpusChar, new: CorpusChar) {
+        if old == new {
+            return;
+        }
+        for affinity in &self.position_affinities[pos] {
+            let affinity = &self.data.affinities[*affinity];
+            let sign = if affinity.char == new {
+                1.0
+            } else if affinity.char == old {
+                -1.0
+            } else {
+                continue;
             };
+            for amount in &affinity.amounts {
+                diffs[amount.metric] += sign * amount.amount;
+            }
+        }
+    }
+
+    /// Like `swap_diff`, but reads the current state of each stroke
+    /// from `cache` instead of recalculating it.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn cached_swap_diff(
+        &self,
+        diffs: &mut [f32],
+        l: &Layout,
+        swap: &Swap,
+        cache: &StrokeCache,
+    ) {
+        if swap.a == swap.b {
+            return;
+        }
+        let (c_a, c_b) = (l.0[swap.a], l.0[swap.b]);
+        self.for_swap_strokes(swap, |stroke, kernel| {
+            let cached = &cache.0[stroke];
+            let new = kernel.swapped_index(cached.index as CorpusIndex, swap, c_a, c_b);
+            self.add_kernel_diff(diffs, kernel, cached.freqs, new);
+        });
+        self.affinity_diff(diffs, swap.a, c_a, c_b);
+        self.affinity_diff(diffs, swap.b, c_b, c_a);
+    }
+
+    /// Builds a `StrokeCache` holding the current state of every
+    /// stroke on `l`.
+    pub(crate) fn stroke_cache(&self, l: &Layout) -> StrokeCache {
+        let mut cache = StrokeCache(vec![CachedStroke::default(); self.kernels.len()]);
+        for stroke in 0..self.kernels.len() {
+            self.refresh_stroke(&mut cache, l, stroke);
+        }
+        cache
+    }
+
+    /// Updates the cached state of every stroke containing one of
+    /// `positions`, after they were changed on `l`.
+    pub(crate) fn update_stroke_cache(
+        &self,
+        cache: &mut StrokeCache,
+        l: &Layout,
+        positions: impl IntoIterator<Item = Pos>,
+    ) {
+        for pos in positions {
+            for stroke in &self.data.position_strokes[pos] {
+                self.refresh_stroke(cache, l, *stroke);
+            }
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn refresh_stroke(&self, cache: &mut StrokeCache, l: &Layout, stroke: NstrokeIndex) {
+        let kernel = &self.kernels[stroke];
+        let index = kernel.index(l);
+        cache.0[stroke] = CachedStroke {
+            index: index as u32,
+            freqs: self.kernel_freqs(kernel, index),
+        };
+    }
+
+    /// Calls `f` on every stroke affected by `swap`, once each.
+    #[inline]
+    fn for_swap_strokes(&self, swap: &Swap, mut f: impl FnMut(NstrokeIndex, &StrokeKernel)) {
+        for stroke in &self.data.position_strokes[swap.a] {
+            f(*stroke, &self.kernels[*stroke]);
+        }
+        for stroke in &self.data.position_strokes[swap.b] {
+            let kernel = &self.kernels[*stroke];
+            // strokes containing both positions were already visited
+            if !kernel.contains(swap.a) {
+                f(*stroke, kernel);
+            }
+        }
+    }
+
+    /// Returns the base and skipgram frequencies at `index` in the
+    /// stroke's tables.
+    #[inline]
+    fn kernel_freqs(&self, kernel: &StrokeKernel, index: CorpusIndex) -> [u32; 2] {
+        match kernel.ngram {
+            NgramType::Monogram => [self.corpus.chars[index], 0],
+            NgramType::Bigram | NgramType::Skipgram => {
+                [self.corpus.bigrams[index], self.corpus.skipgrams[index]]
+            }
+            NgramType::Trigram => [self.corpus.trigrams[index], 0],
+        }
+    }
+
+    /// Adds the diff of a stroke changing from `old_freqs` to the
+    /// frequencies at table index `new`.
+    #[inline]
+    fn add_kernel_diff(
+        &self,
+        diffs: &mut [f32],
+        kernel: &StrokeKernel,
+        old_freqs: [u32; 2],
+        new: CorpusIndex,
+    ) {
+        let new_freqs = self.kernel_freqs(kernel, new);
+        let amounts = &self.kernel_amounts[kernel.amounts as usize..];
+        let (base_amounts, rest) = amounts.split_at(kernel.base_len as usize);
+        let base = i64::from(new_freqs[0]) - i64::from(old_freqs[0]);
+        if base != 0 {
+            for (metric, amount) in base_amounts {
+                diffs[*metric] += amount * base as f32;
+            }
+        }
+        let skip = i64::from(new_freqs[1]) - i64::from(old_freqs[1]);
+        if skip != 0 {
+            for (metric, amount) in &rest[..kernel.skip_len as usize] {
+                diffs[*metric] += amount * skip as f32;
+            }
+        }
+    }
+
+    /// Calculates the diffs of every possible swap on `l` in one
+    /// pass over the strokes. Faster than calling `swap_diff` for
+    /// each pair of positions.
+    #[must_use]
+    pub fn swap_matrix(&self, l: &Layout) -> SwapMatrix {
+        self.cached_swap_matrix(l, &self.calc_stats(l), &self.stroke_cache(l))
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_possible_wrap,
+        clippy::cast_sign_loss
+    )]
+    pub(crate) fn cached_swap_matrix(
+        &self,
+        l: &Layout,
+        stats: &[f32],
+        cache: &StrokeCache,
+    ) -> SwapMatrix {
+        let n = l.0.len();
+        let metrics = self.data.metrics.len();
+        // diffs accumulated per metric, where `planes[m][a * n + b]`
+        // holds the part of swapping `a` and `b` from strokes
+        // containing `a`
+        let mut planes = vec![0.0; metrics * n * n];
+        let mut base_deltas = vec![0.0; n];
+        let mut skip_deltas = vec![0.0; n];
+
+        for (kernel, cached) in self.kernels.iter().zip(&cache.0) {
+            let len = kernel.len as usize;
+            let index = cached.index as CorpusIndex;
+            let [old_base, old_skip] = cached.freqs.map(i64::from);
+            let amounts = &self.kernel_amounts[kernel.amounts as usize..];
+            let (base_amounts, rest) = amounts.split_at(kernel.base_len as usize);
+            let skip_amounts = &rest[..kernel.skip_len as usize];
 
-            let data = &self.data.strokes[*stroke];
-            let ns = &data.nstroke;
-            let basefreqs: [u32; 2] = [
-                l.frequency(&self.corpus, ns, None),
-                match ns {
-                    Nstroke::Monostroke(a) => {
-                        corpus.chars[if *a == swap.a {
-                            c_b
-                        } else if *a == swap.b {
-                            c_a
+            for i in 0..len {
+                let a = kernel.positions[i] as usize;
+                if kernel.positions[..i].iter().any(|p| *p as usize == a) {
+                    continue;
+                }
+                // with `b` outside of the stroke, the index only
+                // changes through the occurrences of `a`
+                let stride: usize = (0..len)
+                    .filter(|j| kernel.positions[*j] as usize == a)
+                    .map(|j| kernel.strides[j] as usize)
+                    .sum();
+                let without_a = index - l.0[a] * stride;
+                match kernel.ngram {
+                    NgramType::Bigram | NgramType::Skipgram => {
+                        for (b, c_b) in l.0.iter().enumerate() {
+                            let new = without_a + c_b * stride;
+                            base_deltas[b] =
+                                (i64::from(self.corpus.bigrams[new]) - old_base) as f32;
+                            skip_deltas[b] =
+                                (i64::from(self.corpus.skipgrams[new]) - old_skip) as f32;
+                        }
+                    }
+                    NgramType::Monogram | NgramType::Trigram => {
+                        let table = if kernel.ngram == NgramType::Monogram {
+                            &self.corpus.chars
                         } else {
-                            l.0[*a]
-                        }]
+                            &self.corpus.trigrams
+                        };
+                        for (b, c_b) in l.0.iter().enumerate() {
+                            base_deltas[b] =
+                                (i64::from(table[without_a + c_b * stride]) - old_base) as f32;
+                        }
                     }
-                    Nstroke::Bistroke(arr) => {
-                        let [a, b]: [usize; 2] = arr.map(|p| {
-                            if p == swap.a {
-                                c_b
-                            } else if p == swap.b {
-                                c_a
-                            } else {
-                                l.0[p]
-                            }
-                        });
-                        corpus.bigrams[corpus.bigram_idx(a, b)]
+                }
+                // swaps within the stroke are calculated separately,
+                // and only once per pair
+                for j in 0..len {
+                    let b = kernel.positions[j] as usize;
+                    base_deltas[b] = 0.0;
+                    skip_deltas[b] = 0.0;
+                    if a < b && !kernel.positions[..j].iter().any(|p| *p as usize == b) {
+                        let swap = Swap::new(a, b);
+                        let new = kernel.swapped_index(index, &swap, l.0[a], l.0[b]);
+                        let [new_base, new_skip] = self.kernel_freqs(kernel, new);
+                        base_deltas[b] = (i64::from(new_base) - old_base) as f32;
+                        skip_deltas[b] = (i64::from(new_skip) - old_skip) as f32;
                     }
-                    Nstroke::Tristroke(arr) => {
-                        let [a, b, c]: [usize; 3] = arr.map(|p| {
-                            if p == swap.a {
-                                c_b
-                            } else if p == swap.b {
-                                c_a
-                            } else {
-                                l.0[p]
-                            }
-                        });
-                        corpus.trigrams[corpus.trigram_idx(a, b, c)]
+                }
+
+                for (deltas, amounts) in
+                    [(&base_deltas, base_amounts), (&skip_deltas, skip_amounts)]
+                {
+                    for (metric, amount) in amounts {
+                        let row = &mut planes[(metric * n + a) * n..(metric * n + a + 1) * n];
+                        for (diff, delta) in row.iter_mut().zip(deltas.iter()) {
+                            *diff += amount * delta;
+                        }
                     }
-                },
-            ];
-            let skipfreqs: [u32; 2] = match ns {
-                Nstroke::Bistroke(arr) => [l.frequency(corpus, ns, Some(NgramType::Skipgram)), {
-                    let [a, b]: [usize; 2] = arr.map(|p| {
-                        if p == swap.a {
-                            c_b
-                        } else if p == swap.b {
-                            c_a
-                        } else {
-                            l.0[p]
+                }
+            }
+            skip_deltas.fill(0.0);
+        }
+
+        for (a, affinities) in self.position_affinities.iter().enumerate() {
+            for affinity in affinities {
+                let affinity = &self.data.affinities[*affinity];
+                for amount in &affinity.amounts {
+                    let row =
+                        &mut planes[(amount.metric * n + a) * n..(amount.metric * n + a + 1) * n];
+                    for (b, diff) in row.iter_mut().enumerate() {
+                        if b == a {
+                            continue;
                         }
-                    });
-                    corpus.skipgrams[corpus.bigram_idx(a, b)]
-                }],
-                _ => [0, 0],
-            };
+                        if affinity.char == l.0[b] {
+                            *diff += amount.amount;
+                        }
+                        if affinity.char == l.0[a] {
+                            *diff -= amount.amount;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut matrix = SwapMatrix::new(n, self.data.stat_count());
+        for a in 0..n {
+            for b in a + 1..n {
+                let row = matrix.row_mut(a, b);
+                for (metric, diff) in row[..metrics].iter_mut().enumerate() {
+                    let plane = &planes[metric * n * n..(metric + 1) * n * n];
+                    *diff = plane[a * n + b] + plane[b * n + a];
+                }
+                self.aggregate_diffs(stats, row);
+            }
+        }
+        matrix
+    }
 
+    /// Calculates the difference in stats caused by applying a
+    /// `Permutation`. Each affected stroke is only evaluated once, no
+    /// matter how many of its positions are moved. Like `swap_diff`,
+    /// the aggregate metrics are left untouched.
+    pub fn permutation_diff(&self, diffs: &mut [f32], l: &Layout, p: &Permutation) {
+        let mut strokes: Vec<NstrokeIndex> = p
+            .positions()
+            .flat_map(|pos| self.data.position_strokes[pos].iter().copied())
+            .collect();
+        strokes.sort_unstable();
+        strokes.dedup();
+
+        let mut permuted = l.clone();
+        permuted.permute(p);
+
+        for stroke in strokes {
+            let data = &self.data.strokes[stroke];
+            let [old_base, old_skip] = self.stroke_freqs(l, &data.nstroke);
+            let [new_base, new_skip] = self.stroke_freqs(&permuted, &data.nstroke);
             for amount in &data.amounts {
-                let diff: f32 = if let NgramType::Skipgram = self.data.metrics[amount.metric] {
-                    Analyzer::diff_freqs(skipfreqs[1], skipfreqs[0])
+                let diff = if let NgramType::Skipgram = self.data.metrics[amount.metric] {
+                    Analyzer::diff_freqs(new_skip, old_skip)
                 } else {
-                    Analyzer::diff_freqs(basefreqs[1], basefreqs[0])
+                    Analyzer::diff_freqs(new_base, old_base)
                 } as f32;
-
-                let real_diff = amount.amount * diff;
-                diffs[amount.metric] += real_diff;
+                diffs[amount.metric] += amount.amount * diff;
             }
         }
+        for pos in p.positions() {
+            self.affinity_diff(diffs, pos, l.0[pos], permuted.0[pos]);
+        }
+    }
+}
+
+/// The diffs of every possible swap on a layout, as calculated by
+/// `Analyzer::swap_matrix`. The diffs of each swap are stored
+/// contiguously, so they can be passed to an objective directly.
+#[derive(Debug, Clone)]
+pub struct SwapMatrix {
+    positions: usize,
+    metrics: usize,
+    /// One row per pair of positions `a < b`, followed by a row of
+    /// zeros for swapping a position with itself.
+    data: Vec<f32>,
+}
+
+impl SwapMatrix {
+    fn new(positions: usize, metrics: usize) -> Self {
+        let pairs = positions * positions.saturating_sub(1) / 2;
+        Self {
+            positions,
+            metrics,
+            data: vec![0.0; (pairs + 1) * metrics],
+        }
+    }
+    fn row_index(&self, a: Pos, b: Pos) -> usize {
+        let (a, b) = (a.min(b), a.max(b));
+        if a == b {
+            self.data.len() / self.metrics.max(1) - 1
+        } else {
+            a * self.positions - a * (a + 1) / 2 + (b - a - 1)
+        }
+    }
+    fn row_mut(&mut self, a: Pos, b: Pos) -> &mut [f32] {
+        let start = self.row_index(a, b) * self.metrics;
+        &mut self.data[start..start + self.metrics]
+    }
+    /// Returns the diffs of `swap`.
+    #[must_use]
+    pub fn diffs(&self, swap: &Swap) -> &[f32] {
+        let start = self.row_index(swap.a, swap.b) * self.metrics;
+        &self.data[start..start + self.metrics]
+    }
+    /// Iterates over every swap of two different positions along
+    /// with its diffs.
+    pub fn iter(&self) -> impl Iterator<Item = (Swap, &[f32])> + '_ {
+        (0..self.positions)
+            .flat_map(move |a| (a + 1..self.positions).map(move |b| Swap::new(a, b)))
+            .map(|swap| {
+                let diffs = self.diffs(&swap);
+                (swap, diffs)
+            })
     }
 }
 
@@ -309,16 +1007,12 @@ mod tests {
         analyzer.swap_diff(&mut diffs, &layout, &Swap::new(0, 1)); // swap q and a
         assert_eq!(-(qa_weight * qa_count as f32), diffs[0]);
 
-        for val in &mut diffs {
-            *val = 0.0;
-        }
+        diffs.fill(0.0);
 
         analyzer.swap_diff(&mut diffs, &layout, &Swap::new(3, 5)); // swap w and x
         assert_eq!(-(ws_weight * ws_count as f32), diffs[0]);
 
-        for val in &mut diffs {
-            *val = 0.0;
-        }
+        diffs.fill(0.0);
 
         analyzer.swap_diff(&mut diffs, &layout, &Swap::new(9, 6));
         assert_eq!(
@@ -327,4 +1021,311 @@ mod tests {
             diffs[0]
         );
     }
+    #[test]
+    fn test_permutation_diff() {
+        let mut corpus = setup_corpus();
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+        let layout = setup_qwerty(&corpus);
+
+        let metrics = vec![NgramType::Bigram, NgramType::Skipgram, NgramType::Trigram];
+        let mut strokes = vec![];
+        for a in 0..30 {
+            for b in 0..30 {
+                if a % 3 != b % 3 {
+                    strokes.push(NstrokeData::new(
+                        Nstroke::Bistroke([a, b]),
+                        vec![MetricAmount::new(0, 1.0), MetricAmount::new(1, 2.0)],
+                    ));
+                }
+                strokes.push(NstrokeData::new(
+                    Nstroke::Tristroke([a, b, (a + b) % 30]),
+                    vec![MetricAmount::new(2, 1.0)],
+                ));
+            }
+        }
+        let analyzer = Analyzer::from(MetricData::from(metrics, strokes, 30), corpus);
+        let before = analyzer.calc_stats(&layout);
+
+        let mirror: Vec<usize> = (0..30).map(|p| (9 - p / 3) * 3 + p % 3).collect();
+        for p in [
+            Permutation::cycle(&[12, 16, 6]),
+            Permutation::swap_sets(&[0, 1, 2], &[27, 28, 29]),
+            Permutation::from_mapping(&mirror),
+        ] {
+            let mut diffs = vec![0.0; before.len()];
+            analyzer.permutation_diff(&mut diffs, &layout, &p);
+
+            let mut permuted = layout.clone();
+            permuted.permute(&p);
+            let after = analyzer.calc_stats(&permuted);
+            for ((b, a), d) in before.iter().zip(&after).zip(&diffs) {
+                assert_eq!(a - b, *d, "{p:?}");
+            }
+        }
+
+        for swap in [Swap::new(0, 1), Swap::new(12, 6), Swap::new(29, 3)] {
+            let mut expected = vec![0.0; before.len()];
+            analyzer.permutation_diff(&mut expected, &layout, &Permutation::from(&swap));
+            let mut diffs = vec![0.0; before.len()];
+            analyzer.swap_diff(&mut diffs, &layout, &swap);
+            assert_eq!(expected, diffs, "{swap:?}");
+        }
+    }
+    #[test]
+    fn test_swap_matrix() {
+        let mut corpus = setup_corpus();
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+        corpus.add_str("pack my box with five dozen liquor jugs");
+        let layout = setup_qwerty(&corpus);
+
+        let metrics = vec![
+            NgramType::Monogram,
+            NgramType::Bigram,
+            NgramType::Skipgram,
+            NgramType::Trigram,
+        ];
+        let mut strokes = vec![];
+        for a in 0..30 {
+            strokes.push(NstrokeData::new(
+                Nstroke::Monostroke(a),
+                vec![MetricAmount::new(0, (a % 4) as f32)],
+            ));
+            for b in 0..30 {
+                if a / 6 == b / 6 {
+                    strokes.push(NstrokeData::new(
+                        Nstroke::Bistroke([a, b]),
+                        vec![MetricAmount::new(1, 1.0), MetricAmount::new(2, 0.5)],
+                    ));
+                    strokes.push(NstrokeData::new(
+                        Nstroke::Tristroke([a, b, a]),
+                        vec![MetricAmount::new(3, 2.0)],
+                    ));
+                }
+            }
+        }
+        let analyzer = Analyzer::from(MetricData::from(metrics, strokes, 30), corpus);
+
+        let matrix = analyzer.swap_matrix(&layout);
+        assert_eq!(435, matrix.iter().count());
+        for (swap, diffs) in matrix.iter() {
+            let mut expected = vec![0.0; diffs.len()];
+            analyzer.swap_diff(&mut expected, &layout, &swap);
+            assert_eq!(expected, diffs, "{swap:?}");
+            assert_eq!(diffs, matrix.diffs(&Swap::new(swap.b, swap.a)));
+        }
+        assert!(matrix.diffs(&Swap::new(4, 4)).iter().all(|d| *d == 0.0));
+
+        // counts past 2^24 can't be subtracted exactly as f32
+        let mut analyzer = analyzer;
+        for table in [&mut analyzer.corpus.bigrams, &mut analyzer.corpus.trigrams] {
+            for freq in table.iter_mut() {
+                *freq += 20_000_000;
+            }
+        }
+        let matrix = analyzer.swap_matrix(&layout);
+        for (swap, diffs) in matrix.iter() {
+            let mut expected = vec![0.0; diffs.len()];
+            analyzer.swap_diff(&mut expected, &layout, &swap);
+            assert_eq!(expected, diffs, "{swap:?}");
+        }
+    }
+    #[test]
+    fn test_aggregates() {
+        let mut corpus = setup_corpus();
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+        let layout = setup_qwerty(&corpus);
+
+        // one usage metric per finger, then left and right hand usage
+        let mut metrics = vec![NgramType::Monogram; 10];
+        metrics.extend([NgramType::Monogram, NgramType::Monogram]);
+        let strokes = (0..30)
+            .map(|p| {
+                NstrokeData::new(
+                    Nstroke::Monostroke(p),
+                    vec![
+                        MetricAmount::new(p / 3, 1.0),
+                        MetricAmount::new(if p < 15 { 10 } else { 11 }, 1.0),
+                    ],
+                )
+            })
+            .collect();
+        let fingers: Vec<MetricIndex> = (0..10).collect();
+        let data = MetricData::from(metrics, strokes, 30).with_aggregates(vec![
+            AggregateMetric::new(Aggregation::Variance, fingers.clone()).expect("inputs"),
+            AggregateMetric::new(Aggregation::Max, fingers).expect("inputs"),
+            AggregateMetric::new(Aggregation::Share, vec![10, 11]).expect("inputs"),
+            AggregateMetric::new(Aggregation::Ratio, vec![10, 11]).expect("two inputs"),
+        ]);
+        let analyzer = Analyzer::from(data, corpus);
+
+        let stats = analyzer.calc_stats(&layout);
+        assert_eq!(16, stats.len());
+        let usage = &stats[..10];
+        let mean = usage.iter().sum::<f32>() / 10.0;
+        let variance = usage.iter().map(|u| (u - mean).powi(2)).sum::<f32>() / 10.0;
+        assert_eq!(variance, stats[12]);
+        assert_eq!(usage.iter().copied().fold(0.0, f32::max), stats[13]);
+        assert_eq!(stats[10] / (stats[10] + stats[11]), stats[14]);
+        assert_eq!(stats[10] / stats[11], stats[15]);
+        assert!(AggregateMetric::new(Aggregation::Ratio, vec![10]).is_err());
+        assert!(AggregateMetric::new(Aggregation::Variance, vec![]).is_err());
+        let ratio = AggregateMetric {
+            aggregation: Aggregation::Ratio,
+            inputs: vec![0, 1],
+        };
+        assert_eq!(0.0, ratio.value(|m| if m == 0 { 1.0 } else { 0.0 }));
+        let empty = AggregateMetric {
+            aggregation: Aggregation::Variance,
+            inputs: vec![],
+        };
+        assert_eq!(0.0, empty.value(|_| 1.0));
+
+        // move e from the left hand to the right
+        let swap = Swap::new(6, 27);
+        let mut diffs = vec![0.0; stats.len()];
+        analyzer.swap_diff(&mut diffs, &layout, &swap);
+        analyzer.aggregate_diffs(&stats, &mut diffs);
+        let mut swapped = layout.clone();
+        swapped.swap(&swap);
+        let after = analyzer.calc_stats(&swapped);
+        for ((b, a), d) in stats.iter().zip(&after).zip(&diffs) {
+            assert!((a - b - d).abs() < 1e-4, "{b} + {d} should be {a}");
+        }
+        assert!(diffs[14] < 0.0, "left hand share should go down");
+
+        let matrix = analyzer.swap_matrix(&layout);
+        assert_eq!(diffs, matrix.diffs(&swap));
+    }
+    #[test]
+    fn test_affinities() {
+        let mut corpus = setup_corpus();
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+        let layout = setup_qwerty(&corpus);
+
+        // keep z and x in place, and keep punctuation on the right
+        let metrics = vec![NgramType::Monogram, NgramType::Monogram];
+        let mut affinities = vec![
+            AffinityData::new(2, corpus.corpus_char('z'), vec![MetricAmount::new(0, -1.0)]),
+            AffinityData::new(5, corpus.corpus_char('x'), vec![MetricAmount::new(0, -1.0)]),
+        ];
+        for pos in 0..15 {
+            for c in [',', '.', '/', ';'] {
+                affinities.push(AffinityData::new(
+                    pos,
+                    corpus.corpus_char(c),
+                    vec![MetricAmount::new(1, 1.0)],
+                ));
+            }
+        }
+        let data = MetricData::from(metrics, vec![], 30).with_affinities(affinities);
+        let analyzer = Analyzer::from(data, corpus);
+
+        assert_eq!(vec![-2.0, 0.0], analyzer.calc_stats(&layout));
+        let matrix = analyzer.swap_matrix(&layout);
+        for (swap, expected) in [
+            (Swap::new(2, 5), [2.0, 0.0]),
+            (Swap::new(2, 29), [1.0, 1.0]),
+            (Swap::new(0, 1), [0.0, 0.0]),
+            (Swap::new(23, 26), [0.0, 0.0]),
+        ] {
+            let mut diffs = vec![0.0; 2];
+            analyzer.swap_diff(&mut diffs, &layout, &swap);
+            assert_eq!(expected.to_vec(), diffs, "{swap:?}");
+            assert_eq!(expected.to_vec(), matrix.diffs(&swap), "{swap:?}");
+        }
+
+        let mut diffs = vec![0.0; 2];
+        let cycle = Permutation::cycle(&[2, 5, 29]);
+        analyzer.permutation_diff(&mut diffs, &layout, &cycle);
+        let mut cycled = layout.clone();
+        cycled.permute(&cycle);
+        assert_eq!(
+            analyzer.calc_stats(&cycled),
+            vec![-2.0 + diffs[0], diffs[1]]
+        );
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut corpus = setup_corpus();
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+        let layout = setup_qwerty(&corpus);
+
+        // serialized before affinities existed
+        let old = r#"{
+            "metrics": ["Bigram"],
+            "strokes": [{"ns": [0, 1], "ams": [{"met": 0, "amt": 1.0}]}],
+            "position_strokes": [[0], [0]]
+        }"#;
+        let data: MetricData = serde_json::from_str(old).expect("old data should load");
+        let analyzer = Analyzer::from(data, corpus);
+        let mut diffs = vec![0.0];
+        analyzer.swap_diff(&mut diffs, &layout, &Swap::new(0, 1));
+        let mut swapped = layout.clone();
+        swapped.swap(&Swap::new(0, 1));
+        assert_eq!(
+            analyzer.calc_stats(&swapped)[0] - analyzer.calc_stats(&layout)[0],
+            diffs[0]
+        );
+
+        let corpus = analyzer.corpus;
+        let affinities = vec![AffinityData::new(
+            2,
+            corpus.corpus_char('z'),
+            vec![MetricAmount::new(0, -1.0)],
+        )];
+        let data =
+            MetricData::from(vec![NgramType::Monogram], vec![], 30).with_affinities(affinities);
+        let json = serde_json::to_string(&data).expect("data should serialize");
+        let data: MetricData = serde_json::from_str(&json).expect("data should load");
+        let analyzer = Analyzer::from(data, corpus);
+        let mut diffs = vec![0.0];
+        analyzer.swap_diff(&mut diffs, &layout, &Swap::new(2, 5));
+        assert_eq!(vec![1.0], diffs);
+    }
+    #[test]
+    fn test_validate() {
+        let corpus = setup_corpus();
+        let qwerty = setup_qwerty(&corpus);
+        let data = MetricData::from(vec![NgramType::Bigram], vec![], 30);
+        let analyzer = Analyzer::from(data, corpus);
+        assert!(analyzer.validate(&qwerty).is_ok());
+
+        let mut chars = qwerty.0.clone();
+        chars.pop();
+        assert_eq!(
+            Err(LayoutError::Length {
+                expected: 30,
+                found: 29
+            }),
+            analyzer.layout(chars.clone()).map(|l| l.0)
+        );
+        chars.push(31);
+        assert_eq!(
+            Err(LayoutError::OutOfRange { pos: 29, char: 31 }),
+            analyzer.layout(chars.clone()).map(|l| l.0)
+        );
+        chars[29] = chars[0];
+        assert_eq!(
+            Err(LayoutError::Duplicate {
+                char: chars[0],
+                first: 0,
+                second: 29
+            }),
+            analyzer.layout(chars.clone()).map(|l| l.0)
+        );
+        // empty positions can repeat, but leave characters missing
+        chars[28] = 0;
+        chars[29] = 0;
+        let partial = analyzer.layout(chars).expect("null characters can repeat");
+        let all: Vec<CorpusChar> = (1..analyzer.corpus.char_list.len()).collect();
+        assert!(qwerty.require(&all).is_ok());
+        assert_eq!(
+            Err(LayoutError::Missing(vec![
+                analyzer.corpus.corpus_char('/'),
+                analyzer.corpus.corpus_char(';')
+            ])),
+            partial.require(&all)
+        );
+    }
 }
@@ -0,0 +1,188 @@
+use crate::analysis::{Analyzer, MetricIndex, NstrokeIndex};
+use crate::{CorpusChar, Layout, LayoutError, Pos, Swap};
+use std::collections::HashMap;
+
+/// A change in how much an ngram contributes to a metric between two
+/// layouts.
+#[derive(Debug, Clone)]
+pub struct NgramShift {
+    pub metric: MetricIndex,
+    pub chars: Vec<CorpusChar>,
+    /// The ngram's frequency in the corpus. For skipgram metrics,
+    /// this is the skipgram frequency.
+    pub frequency: u32,
+    /// The total metric amount of the strokes the ngram occupied in
+    /// the first layout.
+    pub before: f32,
+    /// The total metric amount of the strokes the ngram occupies in
+    /// the second layout.
+    pub after: f32,
+}
+
+impl NgramShift {
+    /// The change in the metric caused by this ngram moving.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn delta(&self) -> f32 {
+        (self.after - self.before) * self.frequency as f32
+    }
+    /// Whether the ngram wasn't counted by the metric before.
+    #[must_use]
+    pub fn moved_in(&self) -> bool {
+        self.before == 0.0 && self.after != 0.0
+    }
+    /// Whether the ngram isn't counted by the metric anymore.
+    #[must_use]
+    pub fn moved_out(&self) -> bool {
+        self.before != 0.0 && self.after == 0.0
+    }
+}
+
+/// Report of the differences between two layouts, produced by
+/// `Analyzer::compare`.
+#[derive(Debug, Clone)]
+pub struct LayoutComparison {
+    /// The stats of the second layout minus those of the first.
+    pub deltas: Vec<f32>,
+    /// The positions that hold different characters.
+    pub changed: Vec<Pos>,
+    /// A shortest swap sequence turning the first layout into the
+    /// second, if they share the same characters.
+    pub swaps: Option<Vec<Swap>>,
+    /// Every ngram whose contribution to a metric changed, sorted by
+    /// the magnitude of the change.
+    pub ngrams: Vec<NgramShift>,
+}
+
+impl LayoutComparison {
+    /// Returns the ngram shifts affecting `metric`.
+    pub fn metric_ngrams(&self, metric: MetricIndex) -> impl Iterator<Item = &NgramShift> {
+        self.ngrams.iter().filter(move |s| s.metric == metric)
+    }
+}
+
+impl Analyzer {
+    /// Compares layout `a` against layout `b`. All differences are
+    /// reported as going from `a` to `b`. Both layouts are checked with
+    /// `Analyzer::validate`, so they have the same length.
+    pub fn compare(&self, a: &Layout, b: &Layout) -> Result<LayoutComparison, LayoutError> {
+        self.validate(a)?;
+        self.validate(b)?;
+        let stats_a = self.calc_stats(a);
+        let stats_b = self.calc_stats(b);
+        let deltas = stats_b.iter().zip(&stats_a).map(|(b, a)| b - a).collect();
+        let changed = a.changed_positions(b);
+
+        // only strokes containing a changed position can differ
+        let mut strokes: Vec<NstrokeIndex> = changed
+            .iter()
+            .flat_map(|p| self.data.position_strokes[*p].iter().copied())
+            .collect();
+        strokes.sort_unstable();
+        strokes.dedup();
+
+        let mut amounts: HashMap<(MetricIndex, Vec<CorpusChar>), (u32, f32, f32)> = HashMap::new();
+        for (layout, is_after) in [(a, false), (b, true)] {
+            for stroke in &strokes {
+                let data = &self.data.strokes[*stroke];
+                let chars = layout.nstroke_chars(&data.nstroke);
+                for amount in &data.amounts {
+                    let entry = amounts
+                        .entry((amount.metric, chars.clone()))
+                        .or_insert_with(|| {
+                            let ng = self.data.metrics[amount.metric];
+                            (
+                                layout.frequency(&self.corpus, &data.nstroke, Some(ng)),
+                                0.0,
+                                0.0,
+                            )
+                        });
+                    if is_after {
+                        entry.2 += amount.amount;
+                    } else {
+                        entry.1 += amount.amount;
+                    }
+                }
+            }
+        }
+
+        let mut ngrams: Vec<NgramShift> = amounts
+            .into_iter()
+            .filter(|(_, (_, before, after))| before != after)
+            .map(|((metric, chars), (frequency, before, after))| NgramShift {
+                metric,
+                chars,
+                frequency,
+                before,
+                after,
+            })
+            .collect();
+        ngrams.sort_by(|x, y| y.delta().abs().total_cmp(&x.delta().abs()));
+
+        Ok(LayoutComparison {
+            deltas,
+            changed,
+            swaps: a.swaps_to(b),
+            ngrams,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{MetricAmount, MetricData, NstrokeData};
+    use crate::{Corpus, NgramType, Nstroke};
+    #[test]
+    fn test_compare() {
+        let mut corpus = Corpus::with_char_list(
+            "abcdefghijklmnopqrstuvwxyz,./;"
+                .chars()
+                .map(|c| vec![c])
+                .collect(),
+        );
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let mut swapped = qwerty.clone();
+        // swap t and e
+        swapped.swap(&Swap::new(12, 6));
+
+        // a single metric penalizing position 12 followed by 16
+        let metrics = vec![NgramType::Bigram];
+        let strokes = vec![NstrokeData::new(
+            Nstroke::Bistroke([12, 16]),
+            vec![MetricAmount::new(0, 1.0)],
+        )];
+        let analyzer = Analyzer::from(MetricData::from(metrics, strokes, 30), corpus);
+
+        let comparison = analyzer
+            .compare(&qwerty, &swapped)
+            .expect("both layouts are valid");
+        assert_eq!(vec![6, 12], comparison.changed);
+        assert_eq!(1, comparison.swaps.as_ref().map_or(0, Vec::len));
+        assert_eq!(-2.0, comparison.deltas[0], "th occurs twice, eh never");
+
+        let th = analyzer.corpus.corpus_bigram(&['t', 'h']);
+        let out: Vec<_> = comparison
+            .metric_ngrams(0)
+            .filter(|s| s.moved_out())
+            .collect();
+        assert_eq!(1, out.len());
+        assert_eq!(
+            th,
+            analyzer.corpus.bigram_idx(out[0].chars[0], out[0].chars[1])
+        );
+        assert_eq!(-2.0, out[0].delta());
+        assert!(comparison.metric_ngrams(0).any(NgramShift::moved_in));
+
+        let short = Layout(qwerty.0[..29].to_vec());
+        assert_eq!(
+            Some(LayoutError::Length {
+                expected: 30,
+                found: 29
+            }),
+            analyzer.compare(&qwerty, &short).err()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{CorpusChar, Layout, Pos, Swap};
+use std::fmt;
+
+/// A hard rule on where characters may be placed. Unlike metrics,
+/// constraints aren't scored: layouts either satisfy them or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constraint {
+    /// Every one of `chars` must be on one of `positions`, e.g. to
+    /// keep characters within a region or restrict them to a set of
+    /// allowed positions.
+    Region {
+        chars: Vec<CorpusChar>,
+        positions: Vec<Pos>,
+    },
+    /// `a` and `b` must be on one of the `(a, b)` position pairs,
+    /// e.g. to keep brackets adjacent.
+    Paired {
+        a: CorpusChar,
+        b: CorpusChar,
+        pairs: Vec<(Pos, Pos)>,
+    },
+    /// All of `chars` must be within the same group of positions,
+    /// e.g. to keep ',' and '.' on the same hand with a group per hand.
+    SameGroup {
+        chars: Vec<CorpusChar>,
+        groups: Vec<Vec<Pos>>,
+    },
+}
+
+impl Constraint {
+    fn involves(&self, c: CorpusChar) -> bool {
+        match self {
+            Constraint::Region { chars, .. } | Constraint::SameGroup { chars, .. } => {
+                chars.contains(&c)
+            }
+            Constraint::Paired { a, b, .. } => *a == c || *b == c,
+        }
+    }
+    /// Checks the constraint, with `char_at` giving the character on
+    /// each of the layout's `len` positions.
+    fn holds(&self, len: usize, char_at: impl Fn(Pos) -> CorpusChar) -> bool {
+        let char_at = &char_at;
+        let positions_of = move |c: CorpusChar| (0..len).filter(move |p| char_at(*p) == c);
+        match self {
+            Constraint::Region { chars, positions } => chars
+                .iter()
+                .all(|c| positions_of(*c).all(|p| positions.contains(&p))),
+            Constraint::Paired { a, b, pairs } => {
+                positions_of(*a).all(|pa| positions_of(*b).all(|pb| pairs.contains(&(pa, pb))))
+            }
+            Constraint::SameGroup { chars, groups } => groups.iter().any(|group| {
+                chars
+                    .iter()
+                    .all(|c| positions_of(*c).all(|p| group.contains(&p)))
+            }),
+        }
+    }
+    /// Whether `l` satisfies the constraint.
+    #[must_use]
+    pub fn is_satisfied(&self, l: &Layout) -> bool {
+        self.holds(l.0.len(), |p| l.0[p])
+    }
+    /// Whether `l` would satisfy the constraint after applying `swap`.
+    #[must_use]
+    pub fn allows_swap(&self, l: &Layout, swap: &Swap) -> bool {
+        let (c_a, c_b) = (l.0[swap.a], l.0[swap.b]);
+        if c_a == c_b || !(self.involves(c_a) || self.involves(c_b)) {
+            return self.is_satisfied(l);
+        }
+        self.holds(l.0.len(), |p| {
+            if p == swap.a {
+                c_b
+            } else if p == swap.b {
+                c_a
+            } else {
+                l.0[p]
+            }
+        })
+    }
+}
+
+/// Returned when a layout breaks a `Constraint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintViolation {
+    /// The index of the broken constraint.
+    pub index: usize,
+    pub constraint: Constraint,
+}
+
+impl fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "layout violates constraint {}: {:?}",
+            self.index, self.constraint
+        )
+    }
+}
+
+impl std::error::Error for ConstraintViolation {}
+
+/// Checks that `l` satisfies every constraint, returning the first
+/// one it violates.
+pub fn check(constraints: &[Constraint], l: &Layout) -> Result<(), ConstraintViolation> {
+    match constraints.iter().position(|c| !c.is_satisfied(l)) {
+        Some(index) => Err(ConstraintViolation {
+            index,
+            constraint: constraints[index].clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Whether applying `swap` to a layout satisfying `constraints` keeps
+/// them satisfied. Constraints not involving the swapped characters
+/// aren't rechecked.
+#[must_use]
+pub fn allows_swap(constraints: &[Constraint], l: &Layout, swap: &Swap) -> bool {
+    let (c_a, c_b) = (l.0[swap.a], l.0[swap.b]);
+    constraints
+        .iter()
+        .filter(|c| c.involves(c_a) || c.involves(c_b))
+        .all(|c| c.allows_swap(l, swap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Corpus;
+    #[test]
+    fn test_constraints() {
+        let corpus = Corpus::with_char_list(
+            "abcdefghijklmnopqrstuvwxyz,./;"
+                .chars()
+                .map(|c| vec![c])
+                .collect(),
+        );
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let c = |ch| corpus.corpus_char(ch);
+
+        let bottom_row: Vec<Pos> = (0..10).map(|col| col * 3 + 2).collect();
+        let hands = vec![(0..15).collect(), (15..30).collect()];
+        let adjacent: Vec<(Pos, Pos)> = (0..27).map(|p| (p, p + 3)).collect();
+        let constraints = vec![
+            Constraint::Region {
+                chars: vec![c('z'), c('x'), c('c'), c('v')],
+                positions: bottom_row,
+            },
+            Constraint::SameGroup {
+                chars: vec![c(','), c('.')],
+                groups: hands,
+            },
+            Constraint::Paired {
+                a: c('z'),
+                b: c('x'),
+                pairs: adjacent,
+            },
+        ];
+        assert!(check(&constraints, &qwerty).is_ok());
+
+        // z and v stay on the bottom row, but aren't adjacent anymore
+        assert!(!allows_swap(&constraints, &qwerty, &Swap::new(2, 11)));
+        // x leaves the bottom row
+        assert!(!allows_swap(&constraints, &qwerty, &Swap::new(5, 4)));
+        // ',' moves to the left hand without '.'
+        assert!(!allows_swap(&constraints, &qwerty, &Swap::new(23, 0)));
+        assert!(allows_swap(&constraints, &qwerty, &Swap::new(23, 29)));
+        assert!(allows_swap(&constraints, &qwerty, &Swap::new(0, 1)));
+
+        let mut broken = qwerty.clone();
+        broken.swap(&Swap::new(23, 0));
+        let violation = check(&constraints, &broken).expect_err("',' was moved");
+        assert_eq!(1, violation.index);
+    }
+}
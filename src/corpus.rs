@@ -1,4 +1,4 @@
-use crate::Layout;
+use crate::{Error, Layout};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
@@ -14,7 +14,7 @@ pub type CorpusIndex = usize;
 pub type CorpusChar = CorpusIndex;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum NgramType {
     Monogram,
     Bigram,
@@ -37,7 +37,7 @@ impl Corpus {
     /// Produces a new `Corpus` with the specified list of
     /// characters. Any characters that are not in the list will be
     /// ignored when counting frequencies.
-
+    ///
     /// ```rust
     /// use keycat::Corpus;
     /// let mut corpus = Corpus::with_char_list(
@@ -106,11 +106,42 @@ impl Corpus {
             self.char_list[c3][0],
         ]
     }
+    /// Like `Corpus::uncorpus_unigram`, but fails if `unigram` isn't a
+    /// character of the `Corpus`.
+    pub fn try_uncorpus_unigram(&self, unigram: CorpusIndex) -> Result<char, Error> {
+        self.check_index(unigram, self.char_list.len())?;
+        Ok(self.uncorpus_unigram(unigram))
+    }
+    /// Like `Corpus::uncorpus_bigram`, but fails if `bigram` is out of
+    /// range.
+    pub fn try_uncorpus_bigram(&self, bigram: CorpusIndex) -> Result<Vec<char>, Error> {
+        self.check_index(bigram, self.bigrams.len())?;
+        Ok(self.uncorpus_bigram(bigram))
+    }
+    /// Like `Corpus::uncorpus_trigram`, but fails if `trigram` is out
+    /// of range.
+    pub fn try_uncorpus_trigram(&self, trigram: CorpusIndex) -> Result<Vec<char>, Error> {
+        self.check_index(trigram, self.trigrams.len())?;
+        Ok(self.uncorpus_trigram(trigram))
+    }
+    fn check_index(&self, index: CorpusIndex, len: usize) -> Result<(), Error> {
+        if index < len {
+            Ok(())
+        } else {
+            Err(Error::IndexOutOfRange { index, len })
+        }
+    }
     /// Converts a `char` to its corresponding index in the `Corpus`.
     #[must_use]
     pub fn corpus_char(&self, c: char) -> CorpusChar {
         *self.char_map.get(&c).unwrap_or(&0)
     }
+    /// Like `Corpus::corpus_char`, but returns `None` for characters
+    /// that aren't in the `Corpus`.
+    #[must_use]
+    pub fn try_corpus_char(&self, c: char) -> Option<CorpusChar> {
+        self.char_map.get(&c).copied()
+    }
     #[must_use]
     pub fn corpus_bigram(&self, chars: &[char; 2]) -> CorpusIndex {
         self.bigram_idx(self.corpus_char(chars[0]), self.corpus_char(chars[1]))
@@ -168,6 +199,8 @@ impl Corpus {
         lines.map_while(Result::ok).for_each(|l| self.add_str(&l));
         Ok(())
     }
+    /// Creates a layout from a flat string. Unknown characters become
+    /// the null character; `format::parse_str` reports them instead.
     #[must_use]
     pub fn layout_from_str(&self, s: &str) -> Layout {
         Layout(s.chars().map(|c| self.corpus_char(c)).collect())
@@ -0,0 +1,277 @@
+use crate::analysis::{Aggregation, MetricIndex};
+use crate::constraint::ConstraintViolation;
+use crate::corpus::CorpusIndex;
+use crate::export::ExportError;
+use crate::format::FormatError;
+#[cfg(feature = "opt")]
+use crate::opt::SetupError;
+use crate::{LayoutError, Pos};
+use std::fmt;
+
+/// Any error returned by keycat, for callers that want to handle
+/// invalid input without matching on each module's error type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Layout(LayoutError),
+    Format(FormatError),
+    Export(ExportError),
+    Constraint(ConstraintViolation),
+    #[cfg(feature = "opt")]
+    Setup(SetupError),
+    /// A position past the end of a layout or the `MetricData`.
+    PositionOutOfRange {
+        pos: Pos,
+        len: usize,
+    },
+    /// An index past the end of one of the `Corpus` frequency tables.
+    IndexOutOfRange {
+        index: CorpusIndex,
+        len: usize,
+    },
+    /// A stroke amount for a metric that doesn't exist.
+    MetricOutOfRange {
+        metric: MetricIndex,
+        len: usize,
+    },
+    /// An aggregate metric with the wrong number of inputs.
+    Arity {
+        aggregation: Aggregation,
+        count: usize,
+    },
+    /// An objective scored a layout as NaN, so it can't be ranked.
+    NanScore,
+    /// A `Permutation` that doesn't move position `pos` to and from
+    /// exactly one position.
+    NotPermutation {
+        pos: Pos,
+    },
+    /// Sets of positions to swap with different lengths.
+    SetLengths {
+        a: usize,
+        b: usize,
+    },
+    /// An annealing acceptance rate outside of (0, 1).
+    Acceptance(f64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Layout(e) => e.fmt(f),
+            Error::Format(e) => e.fmt(f),
+            Error::Export(e) => e.fmt(f),
+            Error::Constraint(e) => e.fmt(f),
+            #[cfg(feature = "opt")]
+            Error::Setup(e) => e.fmt(f),
+            Error::PositionOutOfRange { pos, len } => {
+                write!(f, "position {pos} is out of range for {len} positions")
+            }
+            Error::IndexOutOfRange { index, len } => {
+                write!(f, "corpus index {index} is out of range for {len} entries")
+            }
+            Error::MetricOutOfRange { metric, len } => {
+                write!(f, "metric {metric} is out of range for {len} metrics")
+            }
+            Error::Arity { aggregation, count } => {
+                write!(f, "{aggregation:?} aggregate can't take {count} inputs")
+            }
+            Error::NanScore => write!(f, "objective scored a layout as NaN"),
+            Error::NotPermutation { pos } => {
+                write!(f, "position {pos} isn't moved to and from exactly once")
+            }
+            Error::SetLengths { a, b } => {
+                write!(f, "can't swap a set of {a} positions with a set of {b}")
+            }
+            Error::Acceptance(acceptance) => {
+                write!(f, "acceptance {acceptance} should be between 0 and 1")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Layout(e) => Some(e),
+            Error::Format(e) => Some(e),
+            Error::Export(e) => Some(e),
+            Error::Constraint(e) => Some(e),
+            #[cfg(feature = "opt")]
+            Error::Setup(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<LayoutError> for Error {
+    fn from(e: LayoutError) -> Self {
+        Error::Layout(e)
+    }
+}
+
+impl From<FormatError> for Error {
+    fn from(e: FormatError) -> Self {
+        Error::Format(e)
+    }
+}
+
+impl From<ExportError> for Error {
+    fn from(e: ExportError) -> Self {
+        Error::Export(e)
+    }
+}
+
+impl From<ConstraintViolation> for Error {
+    fn from(e: ConstraintViolation) -> Self {
+        Error::Constraint(e)
+    }
+}
+
+#[cfg(feature = "opt")]
+impl From<SetupError> for Error {
+    fn from(e: SetupError) -> Self {
+        Error::Setup(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{
+        AffinityData, AggregateMetric, Analyzer, MetricAmount, MetricData, NstrokeData,
+    };
+    use crate::{Corpus, NgramType, Nstroke};
+    #[test]
+    fn test_errors() {
+        let mut corpus = Corpus::with_char_list("abc".chars().map(|c| vec![c]).collect());
+        corpus.add_str("abcab");
+        let layout = corpus.layout_from_str("abc");
+        assert_eq!(
+            Some(2),
+            layout
+                .try_frequency(&corpus, &Nstroke::Bistroke([0, 1]), None)
+                .ok()
+        );
+        assert_eq!(
+            Err(Error::PositionOutOfRange { pos: 3, len: 3 }),
+            layout.try_frequency(&corpus, &Nstroke::Bistroke([0, 3]), None)
+        );
+        let invalid = crate::Layout(vec![1, 2, 4]);
+        assert_eq!(
+            Err(Error::Layout(LayoutError::OutOfRange { pos: 2, char: 4 })),
+            invalid.try_frequency(&corpus, &Nstroke::Monostroke(2), None)
+        );
+
+        assert_eq!(Some(vec!['a', 'b']), corpus.try_uncorpus_bigram(6).ok());
+        assert_eq!(
+            Err(Error::IndexOutOfRange { index: 16, len: 16 }),
+            corpus.try_uncorpus_bigram(16)
+        );
+        assert!(corpus.try_uncorpus_unigram(4).is_err());
+        assert!(corpus.try_uncorpus_trigram(63).is_ok());
+
+        let stroke =
+            |nstroke, metric| NstrokeData::new(nstroke, vec![MetricAmount::new(metric, 1.0)]);
+        let metrics = vec![NgramType::Bigram];
+        assert!(MetricData::checked_from(
+            metrics.clone(),
+            vec![stroke(Nstroke::Bistroke([0, 2]), 0)],
+            3
+        )
+        .is_ok());
+        assert_eq!(
+            Some(Error::PositionOutOfRange { pos: 3, len: 3 }),
+            MetricData::checked_from(
+                metrics.clone(),
+                vec![stroke(Nstroke::Bistroke([0, 3]), 0)],
+                3
+            )
+            .err()
+        );
+        assert_eq!(
+            Some(Error::MetricOutOfRange { metric: 1, len: 1 }),
+            MetricData::checked_from(
+                metrics.clone(),
+                vec![stroke(Nstroke::Bistroke([0, 1]), 1)],
+                3
+            )
+            .err()
+        );
+        let data = || MetricData::from(metrics.clone(), vec![], 3);
+        let affinity =
+            |pos, metric| AffinityData::new(pos, 1, vec![MetricAmount::new(metric, 1.0)]);
+        assert!(data().try_with_affinities(vec![affinity(2, 0)]).is_ok());
+        assert_eq!(
+            Some(Error::PositionOutOfRange { pos: 3, len: 3 }),
+            data().try_with_affinities(vec![affinity(3, 0)]).err()
+        );
+        assert_eq!(
+            Some(Error::MetricOutOfRange { metric: 1, len: 1 }),
+            data().try_with_affinities(vec![affinity(0, 1)]).err()
+        );
+        let aggregate = |inputs| AggregateMetric {
+            aggregation: Aggregation::Max,
+            inputs,
+        };
+        assert!(data()
+            .try_with_aggregates(vec![aggregate(vec![0]), aggregate(vec![0, 1])])
+            .is_ok());
+        // an aggregate can't use the one after it
+        assert_eq!(
+            Some(Error::MetricOutOfRange { metric: 2, len: 1 }),
+            data()
+                .try_with_aggregates(vec![aggregate(vec![0, 2]), aggregate(vec![0])])
+                .err()
+        );
+        assert_eq!(
+            Some(Error::Arity {
+                aggregation: Aggregation::Max,
+                count: 0
+            }),
+            data().try_with_aggregates(vec![aggregate(vec![])]).err()
+        );
+        let unchecked = data().with_affinities(vec![affinity(3, 0)]);
+        assert_eq!(
+            Some(Error::PositionOutOfRange { pos: 3, len: 3 }),
+            Analyzer::checked_from(unchecked, Corpus::with_char_list(vec![vec!['a']])).err()
+        );
+
+        #[cfg(feature = "opt")]
+        {
+            use crate::opt::{
+                AnnealingOptimizer, AnonymousObjective, ClosureObjective, GreedyOptimizer,
+                Optimizer, Strategy,
+            };
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            use std::sync::Arc;
+            let data = MetricData::from(vec![NgramType::Bigram], vec![], 3);
+            let analyzer = Analyzer::from(data, corpus);
+            let nan = AnonymousObjective {
+                function: |_| f32::NAN,
+            };
+            let mut optimizer = GreedyOptimizer::new(Strategy::Steepest);
+            optimizer.setup(layout.clone()).expect("no constraints");
+            assert_eq!(
+                Some(Error::NanScore),
+                optimizer.try_run(&analyzer, &nan).err()
+            );
+            let results = optimizer.run(&analyzer, &nan);
+            assert!(results[0].1.is_nan());
+
+            // the starting layouts are scored before anything is run
+            let calls = Arc::new(AtomicUsize::new(0));
+            let counted = Arc::clone(&calls);
+            let nan = ClosureObjective::new(move |_| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                f32::NAN
+            });
+            let mut optimizer = AnnealingOptimizer::new(1, 1000).seed(0);
+            optimizer.setup(layout).expect("no constraints");
+            assert_eq!(
+                Some(Error::NanScore),
+                optimizer.try_run(&analyzer, &nan).err()
+            );
+            assert_eq!(1, calls.load(Ordering::Relaxed));
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use crate::format::Grid;
+use crate::{Corpus, CorpusChar, Layout, Pos};
+use std::fmt::{self, Write};
+
+/// Returned when a layout can't be exported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportError {
+    /// A position placed on a key that isn't on an ANSI keyboard.
+    NoKey { row: usize, column: usize },
+    /// A character the format has no keycode for.
+    Unsupported { char: char, pos: Pos },
+    /// A placed position past the end of the layout.
+    PositionOutOfRange { pos: Pos, len: usize },
+    /// Two positions placed on the same key.
+    DuplicateKey { row: usize, column: usize },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::NoKey { row, column } => {
+                write!(f, "no ANSI key at row {row}, column {column}")
+            }
+            ExportError::Unsupported { char, pos } => {
+                write!(f, "no keycode for {char:?} at position {pos}")
+            }
+            ExportError::PositionOutOfRange { pos, len } => {
+                write!(f, "position {pos} is out of range for {len} positions")
+            }
+            ExportError::DuplicateKey { row, column } => {
+                write!(
+                    f,
+                    "more than one position on the key at row {row}, column {column}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// The number of keys in each row of the ANSI alphanumeric block,
+/// from the number row down, starting at "1", "q", "a" and "z".
+const ROW_LENGTHS: [usize; 4] = [12, 12, 11, 10];
+const XKB_ROWS: [&str; 4] = ["AE", "AD", "AC", "AB"];
+/// The unshifted and shifted characters of the US layout on each row,
+/// which keys a layout doesn't place keep.
+const US_ROWS: [(&str, &str); 4] = [
+    ("1234567890-=", "!@#$%^&*()_+"),
+    ("qwertyuiop[]", "QWERTYUIOP{}"),
+    ("asdfghjkl;'", "ASDFGHJKL:\""),
+    ("zxcvbnm,./", "ZXCVBNM<>?"),
+];
+const MAC_CODES: [&[u16]; 4] = [
+    &[18, 19, 20, 21, 23, 22, 26, 28, 25, 29, 27, 24],
+    &[12, 13, 14, 15, 17, 16, 32, 34, 31, 35, 33, 30],
+    &[0, 1, 2, 3, 5, 4, 38, 40, 37, 41, 39],
+    &[6, 7, 8, 9, 11, 45, 46, 43, 47, 44],
+];
+/// The key codes and outputs of the US keys outside of the ANSI block
+/// that a .keylayout still has to map: grave, backslash, space, return,
+/// tab, delete and escape.
+const MAC_OTHER_KEYS: [(u16, &str, &str); 7] = [
+    (50, "`", "~"),
+    (42, "\\", "|"),
+    (49, " ", " "),
+    (36, "&#x000D;", "&#x000D;"),
+    (48, "&#x0009;", "&#x0009;"),
+    (51, "&#x0008;", "&#x0008;"),
+    (53, "&#x001B;", "&#x001B;"),
+];
+const KLC_SCANCODES: [u8; 4] = [0x02, 0x10, 0x1e, 0x2c];
+/// The scancodes, virtual keys and characters of the US keys outside
+/// of the ANSI block: grave, backslash and space.
+const KLC_OTHER_KEYS: [(u8, &str, char, char); 3] = [
+    (0x29, "OEM_3", '`', '~'),
+    (0x2b, "OEM_5", '\\', '|'),
+    (0x39, "SPACE", ' ', ' '),
+];
+const KLC_VIRTUAL_KEYS: [&[&str]; 4] = [
+    &[
+        "1",
+        "2",
+        "3",
+        "4",
+        "5",
+        "6",
+        "7",
+        "8",
+        "9",
+        "0",
+        "OEM_MINUS",
+        "OEM_PLUS",
+    ],
+    &[
+        "Q", "W", "E", "R", "T", "Y", "U", "I", "O", "P", "OEM_4", "OEM_6",
+    ],
+    &[
+        "A", "S", "D", "F", "G", "H", "J", "K", "L", "OEM_1", "OEM_7",
+    ],
+    &[
+        "Z",
+        "X",
+        "C",
+        "V",
+        "B",
+        "N",
+        "M",
+        "OEM_COMMA",
+        "OEM_PERIOD",
+        "OEM_2",
+    ],
+];
+/// QMK and ZMK keycodes for symbols. Letters and digits are named
+/// after themselves.
+const SYMBOL_KEYCODES: [(char, &str, &str); 32] = [
+    (',', "KC_COMM", "COMMA"),
+    ('.', "KC_DOT", "DOT"),
+    ('/', "KC_SLSH", "FSLH"),
+    (';', "KC_SCLN", "SEMI"),
+    ('\'', "KC_QUOT", "SQT"),
+    ('[', "KC_LBRC", "LBKT"),
+    (']', "KC_RBRC", "RBKT"),
+    ('-', "KC_MINS", "MINUS"),
+    ('=', "KC_EQL", "EQUAL"),
+    ('`', "KC_GRV", "GRAVE"),
+    ('\\', "KC_BSLS", "BSLH"),
+    ('!', "KC_EXLM", "EXCL"),
+    ('@', "KC_AT", "AT"),
+    ('#', "KC_HASH", "HASH"),
+    ('$', "KC_DLR", "DOLLAR"),
+    ('%', "KC_PERC", "PERCENT"),
+    ('^', "KC_CIRC", "CARET"),
+    ('&', "KC_AMPR", "AMPS"),
+    ('*', "KC_ASTR", "STAR"),
+    ('(', "KC_LPRN", "LPAR"),
+    (')', "KC_RPRN", "RPAR"),
+    ('_', "KC_UNDS", "UNDER"),
+    ('+', "KC_PLUS", "PLUS"),
+    ('{', "KC_LCBR", "LBRC"),
+    ('}', "KC_RCBR", "RBRC"),
+    ('|', "KC_PIPE", "PIPE"),
+    (':', "KC_COLN", "COLON"),
+    ('"', "KC_DQUO", "DQT"),
+    ('<', "KC_LT", "LT"),
+    ('>', "KC_GT", "GT"),
+    ('?', "KC_QUES", "QMARK"),
+    ('~', "KC_TILD", "TILDE"),
+];
+
+/// A key of the ANSI alphanumeric block. Rows count down from the
+/// number row, and columns from the leftmost key of the row, so
+/// `AnsiKey::new(1, 0)` is the QWERTY "q" key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnsiKey {
+    row: usize,
+    column: usize,
+}
+
+impl AnsiKey {
+    /// Returns `None` if the key isn't on an ANSI keyboard.
+    #[must_use]
+    pub fn new(row: usize, column: usize) -> Option<Self> {
+        (row < ROW_LENGTHS.len() && column < ROW_LENGTHS[row]).then_some(Self { row, column })
+    }
+    #[must_use]
+    pub fn row(self) -> usize {
+        self.row
+    }
+    #[must_use]
+    pub fn column(self) -> usize {
+        self.column
+    }
+    fn xkb_name(self) -> String {
+        format!("<{}{:02}>", XKB_ROWS[self.row], self.column + 1)
+    }
+    fn mac_code(self) -> u16 {
+        MAC_CODES[self.row][self.column]
+    }
+    fn scancode(self) -> u8 {
+        KLC_SCANCODES[self.row] + self.column as u8
+    }
+}
+
+/// Where each position of a layout is on a keyboard, used to export
+/// layouts to keymap formats. Positions that aren't placed are left
+/// out of the exported keymaps.
+#[derive(Debug, Clone)]
+pub struct Geometry {
+    keys: Vec<(Pos, AnsiKey)>,
+}
+
+impl Geometry {
+    /// Fails if two positions are placed on the same key.
+    pub fn new(keys: Vec<(Pos, AnsiKey)>) -> Result<Self, ExportError> {
+        for (i, (_, key)) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|(_, other)| other == key) {
+                return Err(ExportError::DuplicateKey {
+                    row: key.row,
+                    column: key.column,
+                });
+            }
+        }
+        Ok(Self { keys })
+    }
+    #[must_use]
+    pub fn keys(&self) -> &[(Pos, AnsiKey)] {
+        &self.keys
+    }
+    /// Places the rows of `grid` on the ANSI rows starting at
+    /// `top_row`, aligned to the left of each row. A 3x10 grid placed
+    /// at row 1 covers the QWERTY letter keys.
+    pub fn ansi(grid: &Grid, top_row: usize) -> Result<Self, ExportError> {
+        let mut keys = vec![];
+        for (r, positions) in grid.rows.iter().enumerate() {
+            for (column, pos) in positions.iter().enumerate() {
+                let row = top_row + r;
+                let key = AnsiKey::new(row, column).ok_or(ExportError::NoKey { row, column })?;
+                keys.push((*pos, key));
+            }
+        }
+        Self::new(keys)
+    }
+    /// The placed keys with their unshifted and shifted characters,
+    /// skipping empty positions.
+    fn chars(
+        &self,
+        corpus: &Corpus,
+        l: &Layout,
+    ) -> Result<Vec<(AnsiKey, char, Option<char>)>, ExportError> {
+        let mut chars = vec![];
+        for (pos, key) in &self.keys {
+            let c = char_at(l, *pos)?;
+            if c != 0 {
+                let (c, shifted) = key_chars(corpus, c);
+                chars.push((*key, c, shifted));
+            }
+        }
+        Ok(chars)
+    }
+    /// Every key of the ANSI block with its characters, keeping the US
+    /// layout's for keys that aren't placed or are empty, for formats
+    /// that replace the whole keyboard.
+    fn us_chars(
+        &self,
+        corpus: &Corpus,
+        l: &Layout,
+    ) -> Result<Vec<(AnsiKey, char, Option<char>)>, ExportError> {
+        let mut rows: Vec<Vec<(char, Option<char>)>> = US_ROWS
+            .iter()
+            .map(|(unshifted, shifted)| unshifted.chars().zip(shifted.chars().map(Some)).collect())
+            .collect();
+        for (key, c, shifted) in self.chars(corpus, l)? {
+            rows[key.row][key.column] = (c, shifted);
+        }
+        Ok(rows
+            .into_iter()
+            .enumerate()
+            .flat_map(|(row, keys)| {
+                keys.into_iter()
+                    .enumerate()
+                    .map(move |(column, (c, shifted))| (AnsiKey { row, column }, c, shifted))
+            })
+            .collect())
+    }
+    /// The keys split into rows, for firmware keymaps.
+    fn rows(&self) -> Vec<Vec<(Pos, AnsiKey)>> {
+        let mut rows: Vec<Vec<(Pos, AnsiKey)>> = vec![];
+        for (pos, key) in &self.keys {
+            match rows.last_mut() {
+                Some(row) if row[0].1.row == key.row => row.push((*pos, *key)),
+                _ => rows.push(vec![(*pos, *key)]),
+            }
+        }
+        rows
+    }
+    /// Exports `l` as an XKB symbols file for Linux, which overrides
+    /// the US layout's keys.
+    pub fn to_xkb(&self, corpus: &Corpus, l: &Layout, name: &str) -> Result<String, ExportError> {
+        let mut out = String::new();
+        out.push_str("default partial alphanumeric_keys\n");
+        out.push_str("xkb_symbols \"basic\" {\n");
+        out.push_str("    include \"us(basic)\"\n");
+        let _ = writeln!(out, "    name[Group1] = \"{}\";\n", name.replace('"', "'"));
+        for (key, c, shifted) in self.chars(corpus, l)? {
+            let _ = write!(out, "    key {} {{ [ {}", key.xkb_name(), keysym(c));
+            if let Some(shifted) = shifted {
+                let _ = write!(out, ", {}", keysym(shifted));
+            }
+            out.push_str(" ] };\n");
+        }
+        out.push_str("};\n");
+        Ok(out)
+    }
+    /// Exports `l` as a macOS .keylayout file. Keys the layout doesn't
+    /// place keep their US characters, and the file also maps space,
+    /// return, tab, delete and escape.
+    pub fn to_keylayout(
+        &self,
+        corpus: &Corpus,
+        l: &Layout,
+        name: &str,
+    ) -> Result<String, ExportError> {
+        let chars = self.us_chars(corpus, l)?;
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.1\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<!DOCTYPE keyboard SYSTEM \"file://localhost/System/Library/DTDs/KeyboardLayout.dtd\">\n");
+        let _ = writeln!(
+            out,
+            "<keyboard group=\"126\" id=\"-19341\" name=\"{}\" maxout=\"1\">",
+            xml_escape(name)
+        );
+        out.push_str("    <layouts>\n");
+        out.push_str(
+            "        <layout first=\"0\" last=\"0\" modifiers=\"modifiers\" mapSet=\"keys\"/>\n",
+        );
+        out.push_str("    </layouts>\n");
+        out.push_str("    <modifierMap id=\"modifiers\" defaultIndex=\"0\">\n");
+        out.push_str("        <keyMapSelect mapIndex=\"0\">\n");
+        out.push_str("            <modifier keys=\"\"/>\n");
+        out.push_str("        </keyMapSelect>\n");
+        out.push_str("        <keyMapSelect mapIndex=\"1\">\n");
+        out.push_str("            <modifier keys=\"anyShift caps?\"/>\n");
+        out.push_str("            <modifier keys=\"caps\"/>\n");
+        out.push_str("        </keyMapSelect>\n");
+        out.push_str("    </modifierMap>\n");
+        out.push_str("    <keyMapSet id=\"keys\">\n");
+        for index in 0..2 {
+            let _ = writeln!(out, "        <keyMap index=\"{index}\">");
+            for (key, c, shifted) in &chars {
+                let c = if index == 0 {
+                    *c
+                } else {
+                    shifted.unwrap_or(*c)
+                };
+                let _ = writeln!(
+                    out,
+                    "            <key code=\"{}\" output=\"{}\"/>",
+                    key.mac_code(),
+                    xml_escape(&c.to_string())
+                );
+            }
+            for (code, c, shifted) in MAC_OTHER_KEYS {
+                let c = if index == 0 { c } else { shifted };
+                let _ = writeln!(out, "            <key code=\"{code}\" output=\"{c}\"/>");
+            }
+            out.push_str("        </keyMap>\n");
+        }
+        out.push_str("    </keyMapSet>\n");
+        out.push_str("</keyboard>\n");
+        Ok(out)
+    }
+    /// Exports `l` as a Windows KLC file for the Microsoft Keyboard
+    /// Layout Creator. Virtual keys stay at their QWERTY positions, so
+    /// shortcuts don't move with the letters, and keys the layout
+    /// doesn't place keep their US characters.
+    pub fn to_klc(&self, corpus: &Corpus, l: &Layout, name: &str) -> Result<String, ExportError> {
+        let chars = self.us_chars(corpus, l)?;
+        let mut out = String::new();
+        let _ = writeln!(out, "KBD\tkeycat\t\"{}\"\n", name.replace('"', "'"));
+        out.push_str("COPYRIGHT\t\"\"\n\n");
+        out.push_str("COMPANY\t\"\"\n\n");
+        out.push_str("LOCALENAME\t\"en-US\"\n\n");
+        out.push_str("LOCALEID\t\"00000409\"\n\n");
+        out.push_str("VERSION\t1.0\n\n");
+        out.push_str("SHIFTSTATE\n\n");
+        out.push_str("0\t//Column 4\n");
+        out.push_str("1\t//Column 5 : Shft\n\n");
+        out.push_str("LAYOUT\n\n");
+        out.push_str("//SC\tVK_\t\tCap\t0\t1\n");
+        out.push_str("//--\t----\t\t----\t----\t----\n\n");
+        for (key, c, shifted) in chars {
+            let caps = u8::from(shifted.is_some_and(|s| c.to_uppercase().eq([s])));
+            let shifted = shifted.map_or("-1".to_string(), klc_char);
+            let _ = writeln!(
+                out,
+                "{:02x}\t{}\t\t{}\t{}\t{}",
+                key.scancode(),
+                KLC_VIRTUAL_KEYS[key.row][key.column],
+                caps,
+                klc_char(c),
+                shifted
+            );
+        }
+        for (scancode, virtual_key, c, shifted) in KLC_OTHER_KEYS {
+            let _ = writeln!(
+                out,
+                "{scancode:02x}\t{virtual_key}\t\t0\t{}\t{}",
+                klc_char(c),
+                klc_char(shifted)
+            );
+        }
+        out.push_str("\nENDKBD\n");
+        Ok(out)
+    }
+    /// Exports `l` as the keymap array of a QMK `keymap.c`, with one
+    /// line per row in the argument order of the keyboard's `LAYOUT`
+    /// macro. Characters need a QMK keycode.
+    pub fn to_qmk(&self, corpus: &Corpus, l: &Layout) -> Result<String, ExportError> {
+        let rows = self.keycode_rows(corpus, l, "KC_NO", |c| {
+            letter_or_digit(c)
+                .map(|k| format!("KC_{k}"))
+                .or_else(|| symbol(c).map(|(_, qmk, _)| (*qmk).to_string()))
+        })?;
+        let mut out = String::new();
+        out.push_str("#include QMK_KEYBOARD_H\n\n");
+        out.push_str("const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {\n");
+        out.push_str("    [0] = LAYOUT(\n");
+        let rows: Vec<String> = rows
+            .iter()
+            .map(|row| format!("        {}", row.join(", ")))
+            .collect();
+        out.push_str(&rows.join(",\n"));
+        out.push_str("\n    )\n};\n");
+        Ok(out)
+    }
+    /// Exports `l` as a ZMK `.keymap` devicetree file with a single
+    /// layer. Characters need a ZMK keycode.
+    pub fn to_zmk(&self, corpus: &Corpus, l: &Layout) -> Result<String, ExportError> {
+        let rows = self.keycode_rows(corpus, l, "&none", |c| {
+            letter_or_digit(c)
+                .map(|k| {
+                    if c.is_ascii_digit() {
+                        format!("&kp N{k}")
+                    } else {
+                        format!("&kp {k}")
+                    }
+                })
+                .or_else(|| symbol(c).map(|(_, _, zmk)| format!("&kp {zmk}")))
+        })?;
+        let mut out = String::new();
+        out.push_str("#include <behaviors.dtsi>\n");
+        out.push_str("#include <dt-bindings/zmk/keys.h>\n\n");
+        out.push_str("/ {\n");
+        out.push_str("    keymap {\n");
+        out.push_str("        compatible = \"zmk,keymap\";\n\n");
+        out.push_str("        default_layer {\n");
+        out.push_str("            bindings = <\n");
+        for row in rows {
+            let _ = writeln!(out, "                {}", row.join(" "));
+        }
+        out.push_str("            >;\n");
+        out.push_str("        };\n");
+        out.push_str("    };\n");
+        out.push_str("};\n");
+        Ok(out)
+    }
+    /// The keycodes of each row, with empty positions given `none`.
+    fn keycode_rows(
+        &self,
+        corpus: &Corpus,
+        l: &Layout,
+        none: &str,
+        keycode: impl Fn(char) -> Option<String>,
+    ) -> Result<Vec<Vec<String>>, ExportError> {
+        self.rows()
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|(pos, _)| {
+                        let c = char_at(l, pos)?;
+                        if c == 0 {
+                            return Ok(none.to_string());
+                        }
+                        let (c, _) = key_chars(corpus, c);
+                        keycode(c).ok_or(ExportError::Unsupported { char: c, pos })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn char_at(l: &Layout, pos: Pos) -> Result<CorpusChar, ExportError> {
+    l.0.get(pos)
+        .copied()
+        .ok_or(ExportError::PositionOutOfRange {
+            pos,
+            len: l.0.len(),
+        })
+}
+
+/// The unshifted and shifted characters of `c`. The shifted one is the
+/// second of its characters in the `Corpus`, or its uppercase.
+fn key_chars(corpus: &Corpus, c: CorpusChar) -> (char, Option<char>) {
+    let chars = &corpus.char_list[c];
+    let unshifted = chars[0];
+    let shifted = chars.get(1).copied().or_else(|| {
+        let mut upper = unshifted.to_uppercase();
+        match (upper.next(), upper.next()) {
+            (Some(u), None) if u != unshifted => Some(u),
+            _ => None,
+        }
+    });
+    (unshifted, shifted)
+}
+
+fn letter_or_digit(c: char) -> Option<String> {
+    c.is_ascii_alphanumeric()
+        .then(|| c.to_ascii_uppercase().to_string())
+}
+
+fn symbol(c: char) -> Option<&'static (char, &'static str, &'static str)> {
+    SYMBOL_KEYCODES.iter().find(|entry| entry.0 == c)
+}
+
+fn keysym(c: char) -> String {
+    if c.is_ascii_alphanumeric() {
+        c.to_string()
+    } else {
+        format!("U{:04X}", u32::from(c))
+    }
+}
+
+fn klc_char(c: char) -> String {
+    if c.is_ascii_alphanumeric() {
+        c.to_string()
+    } else {
+        format!("{:04x}", u32::from(c))
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' | '<' | '>' | '"' | '\'' => format!("&#x{:04X};", u32::from(c)),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_export() {
+        let mut chars: Vec<Vec<char>> = "abcdefghijklmnopqrstuvwxyz"
+            .chars()
+            .map(|c| vec![c])
+            .collect();
+        chars.extend([
+            vec![',', '<'],
+            vec!['.', '>'],
+            vec!['/', '?'],
+            vec![';', ':'],
+        ]);
+        chars.push(vec!['é']);
+        let corpus = Corpus::with_char_list(chars);
+        let grid = Grid::row_major(&[10, 10, 10]);
+        let colemak = crate::format::parse_str(&corpus, "qwfpgjluy;arstdhneiozxcvbkm,./")
+            .expect("colemak is valid");
+        let geometry = Geometry::ansi(&grid, 1).expect("3x10 fits on ANSI");
+
+        assert_eq!(
+            Ok(include_str!("../testdata/export/colemak.xkb").to_string()),
+            geometry.to_xkb(&corpus, &colemak, "Colemak")
+        );
+        assert_eq!(
+            Ok(include_str!("../testdata/export/colemak.keylayout").to_string()),
+            geometry.to_keylayout(&corpus, &colemak, "Colemak")
+        );
+        assert_eq!(
+            Ok(include_str!("../testdata/export/colemak.klc").to_string()),
+            geometry.to_klc(&corpus, &colemak, "Colemak")
+        );
+        assert_eq!(
+            Ok(include_str!("../testdata/export/colemak.c").to_string()),
+            geometry.to_qmk(&corpus, &colemak)
+        );
+        assert_eq!(
+            Ok(include_str!("../testdata/export/colemak.keymap").to_string()),
+            geometry.to_zmk(&corpus, &colemak)
+        );
+
+        assert_eq!(
+            Err(ExportError::NoKey { row: 4, column: 0 }),
+            Geometry::ansi(&grid, 2).map(|g| g.keys().to_vec())
+        );
+        let q = AnsiKey::new(1, 0).expect("q is on ANSI");
+        assert_eq!(None, AnsiKey::new(1, 12));
+        assert_eq!(
+            Some(ExportError::DuplicateKey { row: 1, column: 0 }),
+            Geometry::new(vec![(0, q), (1, q)]).err()
+        );
+        let mut accented = colemak.clone();
+        accented.0[9] = corpus.corpus_char('é');
+        assert_eq!(
+            Err(ExportError::Unsupported { char: 'é', pos: 9 }),
+            geometry.to_qmk(&corpus, &accented)
+        );
+        assert!(geometry
+            .to_xkb(&corpus, &accented, "Colemak")
+            .is_ok_and(|xkb| xkb.contains("key <AD10> { [ U00E9, U00C9 ] };")));
+
+        let mut holes = colemak.clone();
+        holes.0[0] = 0;
+        assert!(geometry
+            .to_qmk(&corpus, &holes)
+            .is_ok_and(|qmk| qmk.contains("        KC_NO, KC_W,")));
+        assert!(geometry
+            .to_zmk(&corpus, &holes)
+            .is_ok_and(|zmk| zmk.contains("                &none &kp W")));
+        assert!(geometry
+            .to_klc(&corpus, &holes, "Colemak")
+            .is_ok_and(|klc| klc.contains("10\tQ\t\t1\tq\tQ")));
+        let short = crate::Layout(colemak.0[..20].to_vec());
+        assert_eq!(
+            Err(ExportError::PositionOutOfRange { pos: 20, len: 20 }),
+            geometry.to_keylayout(&corpus, &short, "Colemak")
+        );
+        assert_eq!(
+            Err(ExportError::PositionOutOfRange { pos: 20, len: 20 }),
+            geometry.to_zmk(&corpus, &short)
+        );
+    }
+}
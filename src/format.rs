@@ -0,0 +1,507 @@
+use crate::{Corpus, CorpusChar, Layout, Pos};
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The character used for positions without a character in text
+/// formats, as in the dof format.
+pub const EMPTY: char = '~';
+
+/// Returned when a layout can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// A character that isn't in the `Corpus`.
+    UnknownChar {
+        char: char,
+        pos: Pos,
+    },
+    /// A character that appears at more than one position, possibly
+    /// through another character of the same `CorpusChar`.
+    DuplicateChar {
+        char: char,
+        first: Pos,
+        second: Pos,
+    },
+    RowCount {
+        expected: usize,
+        found: usize,
+    },
+    RowLength {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A key placed outside of the `Grid`.
+    NoPosition {
+        row: usize,
+        column: usize,
+    },
+    /// A position of the layout that no key was given for. Positions
+    /// meant to be empty can be marked with `EMPTY`.
+    Unfilled {
+        pos: Pos,
+    },
+    /// A key none of whose legends are in the `Corpus`.
+    UnknownKey {
+        row: usize,
+        column: usize,
+        label: String,
+    },
+    /// Malformed JSON, or JSON missing a field the format requires.
+    Json(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::UnknownChar { char, pos } => {
+                write!(f, "unknown character {char:?} at position {pos}")
+            }
+            FormatError::DuplicateChar {
+                char,
+                first,
+                second,
+            } => write!(
+                f,
+                "character {char:?} at position {second} is already at position {first}"
+            ),
+            FormatError::RowCount { expected, found } => {
+                write!(f, "expected {expected} rows, found {found}")
+            }
+            FormatError::RowLength {
+                row,
+                expected,
+                found,
+            } => write!(f, "expected {expected} keys in row {row}, found {found}"),
+            FormatError::NoPosition { row, column } => {
+                write!(f, "no position at row {row}, column {column}")
+            }
+            FormatError::Unfilled { pos } => write!(f, "no key for position {pos}"),
+            FormatError::UnknownKey { row, column, label } => {
+                write!(f, "unknown key {label:?} at row {row}, column {column}")
+            }
+            FormatError::Json(message) => write!(f, "invalid layout JSON: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for FormatError {
+    fn from(e: serde_json::Error) -> Self {
+        FormatError::Json(e.to_string())
+    }
+}
+
+/// The shape of a keyboard, mapping each row and column of a layout
+/// file to a `Pos`. Layout formats are read and written through a
+/// `Grid`, since the order of positions depends on the `MetricData`
+/// they're analyzed with.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    pub rows: Vec<Vec<Pos>>,
+}
+
+impl Grid {
+    #[must_use]
+    pub fn new(rows: Vec<Vec<Pos>>) -> Self {
+        Self { rows }
+    }
+    /// A grid with rows of the given lengths, numbering positions
+    /// along each row.
+    #[must_use]
+    pub fn row_major(lengths: &[usize]) -> Self {
+        let mut next = 0;
+        let rows = lengths
+            .iter()
+            .map(|len| {
+                next += len;
+                (next - len..next).collect()
+            })
+            .collect();
+        Self { rows }
+    }
+    /// A `rows` by `columns` grid numbering positions down each column,
+    /// e.g. "qazwsx..." for a 3x10 QWERTY.
+    #[must_use]
+    pub fn column_major(rows: usize, columns: usize) -> Self {
+        let rows = (0..rows)
+            .map(|r| (0..columns).map(|c| c * rows + r).collect())
+            .collect();
+        Self { rows }
+    }
+    /// The length of the layouts described by the grid.
+    #[must_use]
+    pub fn layout_len(&self) -> usize {
+        self.rows.iter().flatten().max().map_or(0, |p| p + 1)
+    }
+    /// Builds a layout from characters at positions, checking that
+    /// each is in the `Corpus` and appears once, and that every
+    /// position is given a character or `EMPTY`.
+    fn assign(
+        &self,
+        corpus: &Corpus,
+        keys: impl IntoIterator<Item = (Pos, char)>,
+    ) -> Result<Layout, FormatError> {
+        let mut layout = vec![0; self.layout_len()];
+        let mut filled = vec![false; layout.len()];
+        let mut seen: HashMap<CorpusChar, Pos> = HashMap::new();
+        for (pos, char) in keys {
+            filled[pos] = true;
+            if char == EMPTY {
+                continue;
+            }
+            let c = corpus
+                .try_corpus_char(char)
+                .ok_or(FormatError::UnknownChar { char, pos })?;
+            if let Some(first) = seen.insert(c, pos) {
+                return Err(FormatError::DuplicateChar {
+                    char,
+                    first,
+                    second: pos,
+                });
+            }
+            layout[pos] = c;
+        }
+        if let Some(pos) = filled.iter().position(|f| !f) {
+            return Err(FormatError::Unfilled { pos });
+        }
+        Ok(Layout(layout))
+    }
+    /// Like `Grid::assign`, checking that the rows have the grid's
+    /// shape.
+    fn assign_rows(&self, corpus: &Corpus, rows: &[Vec<char>]) -> Result<Layout, FormatError> {
+        if rows.len() != self.rows.len() {
+            return Err(FormatError::RowCount {
+                expected: self.rows.len(),
+                found: rows.len(),
+            });
+        }
+        for (row, (keys, positions)) in rows.iter().zip(&self.rows).enumerate() {
+            if keys.len() != positions.len() {
+                return Err(FormatError::RowLength {
+                    row,
+                    expected: positions.len(),
+                    found: keys.len(),
+                });
+            }
+        }
+        let keys = self
+            .rows
+            .iter()
+            .flatten()
+            .zip(rows.iter().flatten())
+            .map(|(pos, c)| (*pos, *c));
+        self.assign(corpus, keys)
+    }
+    /// Parses a layout written as rows of keys, one row per line.
+    /// Whitespace between keys is ignored, so "qwertyuiop" and
+    /// "q w e r t  y u i o p" are the same row.
+    pub fn parse_text(&self, corpus: &Corpus, s: &str) -> Result<Layout, FormatError> {
+        let rows: Vec<Vec<char>> = s
+            .lines()
+            .map(|l| {
+                l.chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect::<Vec<char>>()
+            })
+            .filter(|r| !r.is_empty())
+            .collect();
+        self.assign_rows(corpus, &rows)
+    }
+    /// Prints `l` as rows of keys separated by spaces, which can be
+    /// read back with `Grid::parse_text`.
+    #[must_use]
+    pub fn to_text(&self, corpus: &Corpus, l: &Layout) -> String {
+        self.text_rows(corpus, l).join("\n")
+    }
+    fn text_rows(&self, corpus: &Corpus, l: &Layout) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|p| key_label(corpus, l.0[*p]))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+}
+
+fn key_label(corpus: &Corpus, c: CorpusChar) -> String {
+    if c == 0 {
+        EMPTY.to_string()
+    } else {
+        corpus.uncorpus_unigram(c).to_string()
+    }
+}
+
+/// Parses a layout from a flat string, like `Corpus::layout_from_str`,
+/// but failing on unknown or duplicate characters instead of mapping
+/// them to the null character.
+pub fn parse_str(corpus: &Corpus, s: &str) -> Result<Layout, FormatError> {
+    Grid::row_major(&[s.chars().count()]).assign(corpus, s.chars().enumerate())
+}
+
+#[cfg(feature = "json")]
+impl Grid {
+    fn position(&self, row: usize, column: usize) -> Result<Pos, FormatError> {
+        self.rows
+            .get(row)
+            .and_then(|r| r.get(column))
+            .copied()
+            .ok_or(FormatError::NoPosition { row, column })
+    }
+    /// Parses the main layer of a layout in the dof JSON format, e.g.
+    /// `{"layers": {"main": ["q w e r t  y u i o p", ...]}}`.
+    pub fn parse_dof(&self, corpus: &Corpus, json: &str) -> Result<Layout, FormatError> {
+        let value: Value = serde_json::from_str(json)?;
+        let rows = value["layers"]["main"]
+            .as_array()
+            .ok_or_else(|| FormatError::Json("missing main layer".to_string()))?
+            .iter()
+            .map(|row| {
+                row.as_str()
+                    .map(|s| s.chars().filter(|c| !c.is_whitespace()).collect())
+                    .ok_or_else(|| FormatError::Json("rows should be strings".to_string()))
+            })
+            .collect::<Result<Vec<Vec<char>>, FormatError>>()?;
+        self.assign_rows(corpus, &rows)
+    }
+    /// Prints `l` in the dof JSON format, with `board` naming the
+    /// physical keyboard, e.g. "ortho" or "ansi".
+    #[must_use]
+    pub fn to_dof(&self, corpus: &Corpus, l: &Layout, name: &str, board: &str) -> String {
+        let value = json!({
+            "name": name,
+            "board": board,
+            "layers": { "main": self.text_rows(corpus, l) },
+        });
+        serde_json::to_string_pretty(&value).expect("JSON values always serialize")
+    }
+    /// Parses a layout in the cmini JSON format, which places each
+    /// key by row and column, e.g.
+    /// `{"keys": {"q": {"row": 0, "col": 0, "finger": "LP"}, ...}}`.
+    /// Fingers are ignored, since they're part of the `MetricData`.
+    pub fn parse_cmini(&self, corpus: &Corpus, json: &str) -> Result<Layout, FormatError> {
+        let value: Value = serde_json::from_str(json)?;
+        let keys = value["keys"]
+            .as_object()
+            .ok_or_else(|| FormatError::Json("missing keys".to_string()))?;
+        let mut placed = Vec::with_capacity(keys.len());
+        for (key, info) in keys {
+            let mut chars = key.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                return Err(FormatError::Json(format!(
+                    "key {key:?} isn't one character"
+                )));
+            };
+            let index = |field: &str| {
+                info[field]
+                    .as_u64()
+                    .map(|i| i as usize)
+                    .ok_or_else(|| FormatError::Json(format!("key {key:?} is missing {field}")))
+            };
+            placed.push((self.position(index("row")?, index("col")?)?, c));
+        }
+        // keep errors deterministic regardless of key order
+        placed.sort_unstable();
+        self.assign(corpus, placed)
+    }
+    /// Parses a layout from keyboard-layout-editor raw data. Each key
+    /// takes the first of its legends that's in the `Corpus`, so "!\n1"
+    /// can be either character, and blank keys are `EMPTY`. Key
+    /// properties are skipped, but every key must be on the grid, so
+    /// modifiers and other keys without a legend in the `Corpus` are
+    /// an error.
+    pub fn parse_kle(&self, corpus: &Corpus, json: &str) -> Result<Layout, FormatError> {
+        let value: Value = serde_json::from_str(json)?;
+        let rows = value
+            .as_array()
+            .ok_or_else(|| FormatError::Json("expected an array of rows".to_string()))?;
+        let rows: Vec<Vec<char>> = rows
+            .iter()
+            // objects outside of rows are keyboard metadata
+            .filter_map(Value::as_array)
+            .enumerate()
+            .map(|(row, keys)| {
+                keys.iter()
+                    .filter_map(Value::as_str)
+                    .enumerate()
+                    .map(|(column, label)| {
+                        if label.trim().is_empty() {
+                            return Ok(EMPTY);
+                        }
+                        label
+                            .split('\n')
+                            .filter_map(|legend| {
+                                let mut chars = legend.chars();
+                                match (chars.next(), chars.next()) {
+                                    (Some(c), None) => Some(c),
+                                    _ => None,
+                                }
+                            })
+                            .find(|c| *c == EMPTY || corpus.try_corpus_char(*c).is_some())
+                            .ok_or_else(|| FormatError::UnknownKey {
+                                row,
+                                column,
+                                label: label.to_string(),
+                            })
+                    })
+                    .collect::<Result<Vec<char>, FormatError>>()
+            })
+            .collect::<Result<Vec<Vec<char>>, FormatError>>()?
+            .into_iter()
+            .filter(|row| !row.is_empty())
+            .collect();
+        self.assign_rows(corpus, &rows)
+    }
+    /// Prints `l` as keyboard-layout-editor raw data, with one key per
+    /// position of the grid.
+    #[must_use]
+    pub fn to_kle(&self, corpus: &Corpus, l: &Layout) -> String {
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|p| key_label(corpus, l.0[*p])).collect())
+            .collect();
+        serde_json::to_string(&rows).expect("strings always serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_format() {
+        let corpus = Corpus::with_char_list(
+            "abcdefghijklmnopqrstuvwxyz,./;"
+                .chars()
+                .map(|c| vec![c, c.to_ascii_uppercase()])
+                .collect(),
+        );
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,ol.p;/");
+        let grid = Grid::column_major(3, 10);
+        let text = "q w e r t y u i o p\na s d f g h j k l ;\nz x c v b n m , . /";
+        assert_eq!(text, grid.to_text(&corpus, &qwerty));
+        let parsed = grid
+            .parse_text(&corpus, "qwert yuiop\n\nasdfg hjkl;\nzxcvb nm,./\n")
+            .expect("qwerty is valid");
+        assert_eq!(qwerty.0, parsed.0);
+        assert_eq!(
+            qwerty.0,
+            parse_str(&corpus, "qazwsxedcrfvtgbyhnujmik,ol.p;/")
+                .expect("qwerty is valid")
+                .0
+        );
+
+        assert_eq!(
+            Err(FormatError::UnknownChar { char: '1', pos: 2 }),
+            parse_str(&corpus, "ab1").map(|l| l.0)
+        );
+        assert_eq!(
+            Err(FormatError::DuplicateChar {
+                char: 'A',
+                first: 0,
+                second: 2
+            }),
+            parse_str(&corpus, "abA").map(|l| l.0)
+        );
+        assert_eq!(
+            Err(FormatError::RowCount {
+                expected: 3,
+                found: 2
+            }),
+            grid.parse_text(&corpus, "qwertyuiop\nasdfghjkl;")
+                .map(|l| l.0)
+        );
+        assert_eq!(
+            Err(FormatError::RowLength {
+                row: 1,
+                expected: 10,
+                found: 9
+            }),
+            grid.parse_text(&corpus, "qwertyuiop\nasdfghjkl\nzxcvbnm,./")
+                .map(|l| l.0)
+        );
+        let partial = Grid::row_major(&[3, 2])
+            .parse_text(&corpus, "a~b\nc d")
+            .expect("empty positions are allowed");
+        assert_eq!(
+            Err(FormatError::Unfilled { pos: 1 }),
+            Grid::new(vec![vec![0, 2]])
+                .parse_text(&corpus, "ab")
+                .map(|l| l.0)
+        );
+        assert_eq!(vec![1, 0, 2, 3, 4], partial.0);
+        assert_eq!(
+            "a ~ b\nc d",
+            Grid::row_major(&[3, 2]).to_text(&corpus, &partial)
+        );
+
+        #[cfg(feature = "json")]
+        {
+            let dof = grid.to_dof(&corpus, &qwerty, "qwerty", "ortho");
+            assert_eq!(
+                qwerty.0,
+                grid.parse_dof(&corpus, &dof).expect("valid dof").0
+            );
+            assert!(matches!(
+                grid.parse_dof(&corpus, "{\"layers\": {}}"),
+                Err(FormatError::Json(_))
+            ));
+
+            let cmini = r#"{"name": "abc", "keys": {
+                "a": {"row": 0, "col": 1, "finger": "LI"},
+                "b": {"row": 1, "col": 0, "finger": "RI"}
+            }}"#;
+            let small = Grid::row_major(&[2, 1]);
+            assert_eq!(
+                Err(FormatError::Unfilled { pos: 0 }),
+                small.parse_cmini(&corpus, cmini).map(|l| l.0)
+            );
+            let full = r#"{"name": "abc", "keys": {
+                "a": {"row": 0, "col": 1, "finger": "LI"},
+                "b": {"row": 1, "col": 0, "finger": "RI"},
+                "c": {"row": 0, "col": 0, "finger": "LM"}
+            }}"#;
+            assert_eq!(
+                vec![3, 1, 2],
+                small.parse_cmini(&corpus, full).expect("valid cmini").0
+            );
+            assert_eq!(
+                Err(FormatError::NoPosition { row: 0, column: 1 }),
+                Grid::row_major(&[1, 1])
+                    .parse_cmini(&corpus, cmini)
+                    .map(|l| l.0)
+            );
+
+            let kle = grid.to_kle(&corpus, &qwerty);
+            assert_eq!(
+                qwerty.0,
+                grid.parse_kle(&corpus, &kle).expect("valid kle").0
+            );
+            let kle = r#"[{"name": "board"},
+                [{"w": 1.5}, "!\nA", ""],
+                ["?\n/", {"a": 4}, "b"]]"#;
+            assert_eq!(
+                Ok(vec![1, 0, 29, 2]),
+                Grid::row_major(&[2, 2])
+                    .parse_kle(&corpus, kle)
+                    .map(|l| l.0)
+            );
+            assert_eq!(
+                Err(FormatError::UnknownKey {
+                    row: 1,
+                    column: 1,
+                    label: "Shift".to_string()
+                }),
+                Grid::row_major(&[2, 2])
+                    .parse_kle(&corpus, r#"[["a", "b"], ["c", "Shift"]]"#)
+                    .map(|l| l.0)
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use crate::{Corpus, CorpusChar, NgramType};
+use crate::{Corpus, CorpusChar, Error, NgramType};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 
 pub type Pos = usize;
 
@@ -10,6 +12,50 @@ pub type Pos = usize;
 #[derive(Clone)]
 pub struct Layout(pub Vec<CorpusChar>);
 
+/// Returned when a layout can't be analyzed, or doesn't hold the
+/// characters it should.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The layout doesn't have one character per position of the
+    /// `MetricData`.
+    Length { expected: usize, found: usize },
+    /// A character past the end of the `Corpus` character list.
+    OutOfRange { pos: Pos, char: CorpusChar },
+    /// A character at more than one position. The null character may
+    /// be repeated.
+    Duplicate {
+        char: CorpusChar,
+        first: Pos,
+        second: Pos,
+    },
+    /// Required characters that aren't on the layout.
+    Missing(Vec<CorpusChar>),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Length { expected, found } => {
+                write!(f, "expected {expected} positions, found {found}")
+            }
+            LayoutError::OutOfRange { pos, char } => {
+                write!(f, "character {char} at position {pos} is not in the corpus")
+            }
+            LayoutError::Duplicate {
+                char,
+                first,
+                second,
+            } => write!(
+                f,
+                "character {char} at position {second} is already at position {first}"
+            ),
+            LayoutError::Missing(chars) => write!(f, "missing characters {chars:?}"),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
 impl Layout {
     #[must_use]
     pub fn nstroke_chars(&self, ns: &Nstroke) -> Vec<CorpusChar> {
@@ -35,6 +81,28 @@ impl Layout {
             }
         }
     }
+    /// Like `Layout::frequency`, but fails if the stroke has positions
+    /// past the end of the layout, or the layout has characters that
+    /// aren't in the `Corpus`.
+    pub fn try_frequency(
+        &self,
+        corpus: &Corpus,
+        ns: &Nstroke,
+        ng: Option<NgramType>,
+    ) -> Result<u32, Error> {
+        for pos in ns.to_vec() {
+            let Some(&char) = self.0.get(pos) else {
+                return Err(Error::PositionOutOfRange {
+                    pos,
+                    len: self.0.len(),
+                });
+            };
+            if char >= corpus.char_list.len() {
+                return Err(LayoutError::OutOfRange { pos, char }.into());
+            }
+        }
+        Ok(self.frequency(corpus, ns, ng))
+    }
     #[must_use]
     pub fn total_char_count(&self, corpus: &Corpus) -> u32 {
         self.0.iter().map(|c| corpus.chars[*c]).sum()
@@ -42,7 +110,7 @@ impl Layout {
     fn bi_count(&self, corpus: &Corpus, frequencies: &[u32]) -> u32 {
         self.0
             .iter()
-            .flat_map(|a| (self.0.iter().map(move |b| (a, b))))
+            .flat_map(|a| self.0.iter().map(move |b| (a, b)))
             .map(|(a, b)| frequencies[corpus.bigram_idx(*a, *b)])
             .sum()
     }
@@ -58,23 +126,88 @@ impl Layout {
     pub fn total_trigram_count(&self, corpus: &Corpus) -> u32 {
         self.0
             .iter()
-            .flat_map(|a| (self.0.iter().map(move |b| (a, b))))
-            .flat_map(|(a, b)| (self.0.iter().map(move |c| (a, b, c))))
+            .flat_map(|a| self.0.iter().map(move |b| (a, b)))
+            .flat_map(|(a, b)| self.0.iter().map(move |c| (a, b, c)))
             .map(|(a, b, c)| corpus.trigrams[corpus.trigram_idx(*a, *b, *c)])
             .sum()
     }
     #[must_use]
     pub fn totals(&self, corpus: &Corpus) -> LayoutTotals {
         LayoutTotals {
-            chars: self.total_char_count(&corpus),
-            bigrams: self.total_bigram_count(&corpus),
-            skipgrams: self.total_skipgram_count(&corpus),
-            trigrams: self.total_trigram_count(&corpus),
+            chars: self.total_char_count(corpus),
+            bigrams: self.total_bigram_count(corpus),
+            skipgrams: self.total_skipgram_count(corpus),
+            trigrams: self.total_trigram_count(corpus),
+        }
+    }
+    /// Checks that every character of `chars` is on the layout.
+    pub fn require(&self, chars: &[CorpusChar]) -> Result<(), LayoutError> {
+        let missing: Vec<CorpusChar> = chars
+            .iter()
+            .filter(|c| !self.0.contains(c))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(LayoutError::Missing(missing))
         }
     }
     pub fn swap(&mut self, s: &Swap) {
         self.0.swap(s.a, s.b);
     }
+    pub fn permute(&mut self, p: &Permutation) {
+        let moved: Vec<CorpusChar> = p.moves.iter().map(|(_, from)| self.0[*from]).collect();
+        for ((to, _), c) in p.moves.iter().zip(moved) {
+            self.0[*to] = c;
+        }
+    }
+    /// Returns the positions holding a different character in
+    /// `other`.
+    #[must_use]
+    pub fn changed_positions(&self, other: &Layout) -> Vec<Pos> {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, _)| i)
+            .collect()
+    }
+    /// Finds a shortest sequence of swaps that transforms this layout
+    /// into `other`, or `None` if the layouts don't contain the same
+    /// characters. The sequence is minimal when no character appears
+    /// more than once.
+    #[must_use]
+    pub fn swaps_to(&self, other: &Layout) -> Option<Vec<Swap>> {
+        let mut sorted_a = self.0.clone();
+        let mut sorted_b = other.0.clone();
+        sorted_a.sort_unstable();
+        sorted_b.sort_unstable();
+        if sorted_a != sorted_b {
+            return None;
+        }
+
+        let mut current = self.clone();
+        let mut swaps = vec![];
+        for i in self.changed_positions(other) {
+            if current.0[i] == other.0[i] {
+                continue;
+            }
+            let wanted = other.0[i];
+            let misplaced = |j: &usize| current.0[*j] == wanted && current.0[*j] != other.0[*j];
+            // prefer a swap that puts both characters in place,
+            // closing a 2-cycle
+            let j = (i + 1..self.0.len())
+                .filter(misplaced)
+                .find(|j| other.0[*j] == current.0[i])
+                .or_else(|| (i + 1..self.0.len()).find(misplaced))?;
+            let swap = Swap::new(i, j);
+            current.swap(&swap);
+            swaps.push(swap);
+        }
+        Some(swaps)
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
@@ -109,6 +242,125 @@ impl Swap {
     }
 }
 
+/// An arbitrary rearrangement of positions, such as a k-cycle, a
+/// column swap, or a mirror. Generalizes `Swap` to any number of
+/// positions.
+#[derive(Debug, Clone)]
+pub struct Permutation {
+    /// Pairs of `(to, from)`: the character at `from` is moved to
+    /// `to`. Positions not listed stay in place.
+    moves: Vec<(Pos, Pos)>,
+}
+
+impl Permutation {
+    fn from_moves(mut moves: Vec<(Pos, Pos)>) -> Result<Self, Error> {
+        moves.retain(|(to, from)| to != from);
+        let p = Self { moves };
+        match p.invalid_position() {
+            Some(pos) => Err(Error::NotPermutation { pos }),
+            None => Ok(p),
+        }
+    }
+    /// Moves the character at each position to the next one in
+    /// `positions`, and the last one back to the first.
+    ///
+    /// Panics if a position appears more than once.
+    #[must_use]
+    pub fn cycle(positions: &[Pos]) -> Self {
+        Self::try_cycle(positions).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Like `Permutation::cycle`, but fails if a position appears
+    /// more than once.
+    pub fn try_cycle(positions: &[Pos]) -> Result<Self, Error> {
+        let n = positions.len();
+        Self::from_moves(
+            (0..n)
+                .map(|i| (positions[(i + 1) % n], positions[i]))
+                .collect(),
+        )
+    }
+    /// Exchanges each position in `a` with the position at the same
+    /// index in `b`, e.g. to swap two columns or rows.
+    ///
+    /// Panics if `a` and `b` have different lengths or overlap.
+    #[must_use]
+    pub fn swap_sets(a: &[Pos], b: &[Pos]) -> Self {
+        Self::try_swap_sets(a, b).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Like `Permutation::swap_sets`, but fails if `a` and `b` have
+    /// different lengths or overlap.
+    pub fn try_swap_sets(a: &[Pos], b: &[Pos]) -> Result<Self, Error> {
+        if a.len() != b.len() {
+            return Err(Error::SetLengths {
+                a: a.len(),
+                b: b.len(),
+            });
+        }
+        Self::from_moves(
+            a.iter()
+                .zip(b)
+                .flat_map(|(a, b)| [(*a, *b), (*b, *a)])
+                .collect(),
+        )
+    }
+    /// Builds a permutation where position `i` receives the
+    /// character from position `mapping[i]`. A mirror is a mapping
+    /// from each position to its mirrored counterpart.
+    ///
+    /// Panics if two positions receive the same character.
+    #[must_use]
+    pub fn from_mapping(mapping: &[Pos]) -> Self {
+        Self::try_from_mapping(mapping).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Like `Permutation::from_mapping`, but fails if two positions
+    /// receive the same character.
+    pub fn try_from_mapping(mapping: &[Pos]) -> Result<Self, Error> {
+        Self::from_moves(
+            mapping
+                .iter()
+                .enumerate()
+                .map(|(to, from)| (to, *from))
+                .collect(),
+        )
+    }
+    /// Whether every position is moved to exactly once and moved from
+    /// exactly once.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.invalid_position().is_none()
+    }
+    /// The lowest position that isn't moved to exactly once and moved
+    /// from exactly once.
+    fn invalid_position(&self) -> Option<Pos> {
+        let mut counts: BTreeMap<Pos, (usize, usize)> = BTreeMap::new();
+        for (to, from) in &self.moves {
+            counts.entry(*to).or_default().0 += 1;
+            counts.entry(*from).or_default().1 += 1;
+        }
+        counts
+            .into_iter()
+            .find(|(_, count)| *count != (1, 1))
+            .map(|(pos, _)| pos)
+    }
+    /// The positions whose characters change.
+    pub fn positions(&self) -> impl Iterator<Item = Pos> + '_ {
+        self.moves.iter().map(|m| m.0)
+    }
+    /// Returns the permutation that undoes this one.
+    #[must_use]
+    pub fn inverse(&self) -> Self {
+        Self {
+            moves: self.moves.iter().map(|(to, from)| (*from, *to)).collect(),
+        }
+    }
+}
+
+impl From<&Swap> for Permutation {
+    fn from(s: &Swap) -> Self {
+        Self::swap_sets(&[s.a], &[s.b])
+    }
+}
+
 #[derive(Debug)]
 pub struct LayoutTotals {
     chars: u32,
@@ -142,7 +394,7 @@ mod tests {
         );
 
         let text = "the quick brown fox jumps over the lazy dog";
-        corpus.add_str(&text);
+        corpus.add_str(text);
 
         let mut qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
 
@@ -179,5 +431,53 @@ mod tests {
             text.chars().filter(|c| *c != ' ').collect::<Vec<_>>().len() as u32,
             qwerty.total_char_count(&corpus)
         );
+
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let colemak = corpus.layout_from_str("qazwrxfscptvgdbjhklnmue,yi.;o/");
+        let swaps = qwerty.swaps_to(&colemak).expect("same characters");
+        let mut transformed = qwerty.clone();
+        for swap in &swaps {
+            transformed.swap(swap);
+        }
+        assert_eq!(colemak.0, transformed.0);
+        assert_eq!(17, qwerty.changed_positions(&colemak).len());
+        assert_eq!(16, swaps.len(), "17 keys in a single cycle need 16 swaps");
+        assert!(qwerty.swaps_to(&corpus.layout_from_str("abc")).is_none());
+
+        let mut cycled = qwerty.clone();
+        let cycle = Permutation::cycle(&[0, 1, 2]);
+        cycled.permute(&cycle);
+        assert_eq!(
+            corpus.layout_from_str("zqawsxedcrfvtgbyhnujmik,lo.p;/").0,
+            cycled.0
+        );
+        cycled.permute(&cycle.inverse());
+        assert_eq!(qwerty.0, cycled.0);
+
+        let mut columns = qwerty.clone();
+        columns.permute(&Permutation::swap_sets(&[0, 1, 2], &[3, 4, 5]));
+        assert_eq!(
+            corpus.layout_from_str("wsxqazedcrfvtgbyhnujmik,lo.p;/").0,
+            columns.0
+        );
+        let mirror: Vec<Pos> = (0..30).map(|p| (9 - p / 3) * 3 + p % 3).collect();
+        let mut mirrored = qwerty.clone();
+        mirrored.permute(&Permutation::from_mapping(&mirror));
+        assert_eq!(
+            corpus.layout_from_str("p;/lo.ik,ujmyhntgbrfvedcwsxqaz").0,
+            mirrored.0
+        );
+        assert_eq!(
+            Some(Error::NotPermutation { pos: 1 }),
+            Permutation::try_cycle(&[0, 1, 2, 1]).err()
+        );
+        assert_eq!(
+            Some(Error::SetLengths { a: 2, b: 1 }),
+            Permutation::try_swap_sets(&[0, 1], &[2]).err()
+        );
+        assert_eq!(
+            Some(Error::NotPermutation { pos: 0 }),
+            Permutation::try_from_mapping(&[1, 1, 2]).err()
+        );
     }
 }
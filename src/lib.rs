@@ -1,7 +1,14 @@
 pub mod analysis;
+pub mod compare;
+pub mod constraint;
 pub mod corpus;
+mod error;
+pub mod export;
+pub mod format;
 pub mod layout;
 #[cfg(feature = "opt")]
 pub mod opt;
+pub mod state;
 pub use corpus::{Corpus, CorpusChar, NgramType};
-pub use layout::{Layout, Nstroke, Pos, Swap};
+pub use error::Error;
+pub use layout::{Layout, LayoutError, Nstroke, Permutation, Pos, Swap};
@@ -0,0 +1,545 @@
+use super::{
+    by_score, check_results, check_scores, possible_swaps, seeded_rng, Init, Objective, Optimizer,
+    Progress, RunControl, SetupError,
+};
+use crate::constraint::{self, Constraint};
+use crate::{analysis::Analyzer, state::LayoutState, Error, Layout, Swap};
+use rand::prelude::*;
+use rayon::prelude::*;
+
+/// How the temperature of an `AnnealingOptimizer` changes over a
+/// run.
+#[derive(Debug, Clone, Copy)]
+pub enum Schedule {
+    /// Decreases linearly from the initial temperature to zero.
+    Linear,
+    /// Multiplies the temperature by a constant factor every
+    /// iteration, ending at `final_ratio` times the initial
+    /// temperature.
+    Exponential { final_ratio: f64 },
+    /// Cools like `Exponential`, but multiplies the temperature by
+    /// `reheat` whenever the best score hasn't improved for `patience`
+    /// iterations.
+    Adaptive {
+        final_ratio: f64,
+        patience: u64,
+        reheat: f64,
+    },
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::Exponential { final_ratio: 1e-3 }
+    }
+}
+
+/// An `Optimizer` that runs simulated annealing on a pool of size
+/// `population_size`. Swaps are accepted with the Metropolis
+/// criterion: always if they improve the score, and otherwise with
+/// probability `exp(-diff / temperature)`.
+///
+/// Members that end on the same layout as another member can be
+/// restarted from a new starting layout, see
+/// `AnnealingOptimizer::restarts`.
+pub struct AnnealingOptimizer {
+    layouts: Vec<Layout>,
+    /// The layout passed to `setup`, which restarts start from.
+    start: Option<Layout>,
+    pins: Vec<usize>,
+    constraints: Vec<Constraint>,
+    /// The swaps that don't move a pinned position, found in `setup`.
+    swaps: Vec<Swap>,
+    control: RunControl,
+    seed: Option<u64>,
+    /// The number of layouts to be optimized in parallel.
+    pub population_size: usize,
+    /// The number of iterations, i.e. swaps to make before finishing.
+    pub iterations: u64,
+    pub schedule: Schedule,
+    /// The starting temperature. If `None`, it is calibrated from a
+    /// sample of swaps so that `initial_acceptance` of the worsening
+    /// swaps are accepted at the start.
+    pub initial_temperature: Option<f64>,
+    /// Set with `AnnealingOptimizer::with_acceptance`.
+    initial_acceptance: f64,
+    /// How members' starting layouts are created.
+    pub init: Init,
+    /// How many times members that converged to the same layout as
+    /// another member are restarted. Each restart anneals them again
+    /// from a new starting layout created with `init`.
+    pub restarts: u32,
+}
+
+impl AnnealingOptimizer {
+    /// The number of swaps sampled to calibrate the temperature.
+    const CALIBRATION_SAMPLES: usize = 200;
+
+    #[must_use]
+    pub fn new(population_size: usize, iterations: u64) -> Self {
+        Self {
+            layouts: Vec::with_capacity(population_size),
+            start: None,
+            pins: vec![],
+            constraints: vec![],
+            swaps: vec![],
+            control: RunControl::default(),
+            seed: None,
+            population_size,
+            iterations,
+            schedule: Schedule::default(),
+            initial_temperature: None,
+            initial_acceptance: 0.8,
+            init: Init::default(),
+            restarts: 0,
+        }
+    }
+    #[must_use]
+    pub fn with_init(mut self, init: Init) -> Self {
+        self.init = init;
+        self
+    }
+    #[must_use]
+    pub fn with_restarts(mut self, restarts: u32) -> Self {
+        self.restarts = restarts;
+        self
+    }
+    #[must_use]
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+    /// Calibrates the starting temperature so that `acceptance` of
+    /// the worsening swaps are accepted at the start.
+    ///
+    /// Panics if `acceptance` isn't between 0 and 1, exclusive.
+    #[must_use]
+    pub fn with_acceptance(self, acceptance: f64) -> Self {
+        self.try_with_acceptance(acceptance)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Like `AnnealingOptimizer::with_acceptance`, but fails if
+    /// `acceptance` isn't between 0 and 1, exclusive.
+    pub fn try_with_acceptance(mut self, acceptance: f64) -> Result<Self, Error> {
+        if !(acceptance > 0.0 && acceptance < 1.0) {
+            return Err(Error::Acceptance(acceptance));
+        }
+        self.initial_acceptance = acceptance;
+        Ok(self)
+    }
+    #[must_use]
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.initial_temperature = Some(temperature);
+        self
+    }
+    /// The swaps the pins and constraints allow on `l`.
+    fn allowed_swaps(&self, l: &Layout) -> Vec<Swap> {
+        self.swaps
+            .iter()
+            .filter(|swap| constraint::allows_swap(&self.constraints, l, swap))
+            .cloned()
+            .collect()
+    }
+    /// Estimates a starting temperature from the mean worsening diff
+    /// of random swaps on `state`.
+    fn calibrate(
+        &self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        state: &mut LayoutState,
+        swaps: &[Swap],
+        rng: &mut impl Rng,
+    ) -> f64 {
+        let worsening: Vec<f64> = swaps
+            .choose_multiple(rng, Self::CALIBRATION_SAMPLES)
+            .map(|swap| {
+                let diffs = state.swap_diff(analyzer, swap).to_vec();
+                f64::from(objective.score_transition(state.stats(), &diffs))
+            })
+            .filter(|diff| *diff > 0.0)
+            .collect();
+        if worsening.is_empty() {
+            return 1.0;
+        }
+        let mean = worsening.iter().sum::<f64>() / worsening.len() as f64;
+        -mean / self.initial_acceptance.ln()
+    }
+    /// Anneals the layout of population member `member`.
+    fn anneal(
+        &self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        member: usize,
+        state: &mut LayoutState,
+        rng: &mut impl Rng,
+    ) {
+        // only recalculated when constraints can change which swaps
+        // are allowed
+        let mut allowed = self.allowed_swaps(state.layout());
+        if allowed.is_empty() {
+            return;
+        }
+        let initial = self
+            .initial_temperature
+            .unwrap_or_else(|| self.calibrate(analyzer, objective, state, &allowed, rng));
+        let iterations = self.iterations as f64;
+        let cooling = match self.schedule {
+            Schedule::Linear => 1.0,
+            Schedule::Exponential { final_ratio } | Schedule::Adaptive { final_ratio, .. } => {
+                final_ratio.powf(1.0 / iterations)
+            }
+        };
+
+        let mut diffs = vec![0.0; state.stats().len()];
+        let mut temp = initial;
+        let start = f64::from(objective.score(state.stats()));
+        // the score relative to the starting layout
+        let mut score = 0.0;
+        let mut best = 0.0;
+        let mut best_layout = state.layout().clone();
+        let mut last_improvement = 0;
+        for i in 0..self.iterations {
+            if self.control.should_stop(i) {
+                break;
+            }
+            let Some(swap) = allowed.choose(rng).cloned() else {
+                break;
+            };
+            diffs.copy_from_slice(state.swap_diff(analyzer, &swap));
+            let diff = f64::from(objective.score_transition(state.stats(), &diffs));
+            if diff <= 0.0 || (temp > 0.0 && rng.gen::<f64>() < (-diff / temp).exp()) {
+                state.apply(analyzer, &swap, &diffs);
+                state.commit();
+                score += diff;
+                if !self.constraints.is_empty() {
+                    allowed = self.allowed_swaps(state.layout());
+                }
+            }
+            if score < best {
+                best = score;
+                best_layout = state.layout().clone();
+                last_improvement = i;
+            }
+            if self.control.should_report(i + 1) {
+                self.control.report(&Progress {
+                    member,
+                    iteration: i + 1,
+                    best_score: (start + best) as f32,
+                    best_layout: &best_layout,
+                });
+            }
+            temp = match self.schedule {
+                Schedule::Linear => initial * (1.0 - (i + 1) as f64 / iterations),
+                Schedule::Exponential { .. } => temp * cooling,
+                Schedule::Adaptive {
+                    patience, reheat, ..
+                } => {
+                    if i - last_improvement >= patience {
+                        last_improvement = i;
+                        (temp * cooling * reheat).min(initial)
+                    } else {
+                        temp * cooling
+                    }
+                }
+            };
+        }
+        // reheating may leave the layout worse than the best one seen
+        if best < score {
+            *state = LayoutState::new(analyzer, best_layout);
+        }
+    }
+}
+
+impl Optimizer for AnnealingOptimizer {
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError> {
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        self.layouts = self.init.population(
+            &l,
+            self.population_size,
+            &self.pins,
+            &self.constraints,
+            seed,
+        )?;
+        self.swaps = possible_swaps(l.0.len(), &self.pins);
+        self.start = Some(l);
+        Ok(())
+    }
+
+    fn pin(mut self, pins: Vec<usize>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    fn constrain(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    fn control(mut self, control: RunControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)> {
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let population = std::mem::take(&mut self.layouts);
+        let size = population.len();
+        let run_member = |member: usize, round: usize, l: Layout| {
+            let mut state = LayoutState::new(analyzer, l);
+            let mut rng = seeded_rng(seed, (round * size + member) as u64);
+            self.anneal(analyzer, objective, member, &mut state, &mut rng);
+            let score = objective.score(state.stats());
+            (state.into_layout(), score)
+        };
+        let mut layouts: Vec<(Layout, f32)> = population
+            .into_par_iter()
+            .enumerate()
+            .map(|(member, l)| run_member(member, 0, l))
+            .collect();
+
+        for round in 1..=self.restarts as usize {
+            let Some(start) = &self.start else {
+                break;
+            };
+            let converged: Vec<usize> = (0..size)
+                .filter(|i| (0..*i).any(|j| layouts[j].0 .0 == layouts[*i].0 .0))
+                .collect();
+            if converged.is_empty() || self.control.is_cancelled() {
+                break;
+            }
+            let restarted: Vec<(usize, (Layout, f32))> = converged
+                .into_par_iter()
+                .map(|member| {
+                    let index = round * size + member;
+                    let l = (self.init).member(start, index, &self.pins, &self.constraints, seed);
+                    (member, run_member(member, round, l))
+                })
+                .collect();
+            for (member, result) in restarted {
+                layouts[member] = result;
+            }
+        }
+
+        self.layouts = layouts.iter().map(|(l, _)| l.clone()).collect();
+        layouts.sort_by(|a, b| by_score(a.1, b.1));
+        layouts
+    }
+
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_scores(analyzer, objective, &self.layouts)?;
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::{tests::setup_analyzer, Weight, WeightsObjective};
+    #[test]
+    fn test_optimization() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        let start = objective.score(&analyzer.calc_stats(&qwerty));
+        let run = || {
+            let mut optimizer = AnnealingOptimizer::new(4, 1000).pin(vec![0]).seed(7);
+            optimizer
+                .setup(qwerty.clone())
+                .expect("no constraints to break");
+            optimizer.run(&analyzer, &objective)
+        };
+        let optimized = run();
+        let end = &optimized[0].1;
+        assert!(
+            *end < start,
+            "optimized should be lower score than unoptimized"
+        );
+        for ((a, score_a), (b, score_b)) in optimized.iter().zip(run()) {
+            assert_eq!(a.0, b.0, "runs with the same seed should match");
+            assert_eq!(*score_a, score_b);
+        }
+    }
+    #[test]
+    fn test_constraints() {
+        let analyzer = setup_analyzer();
+        let corpus = &analyzer.corpus;
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let c = |ch| corpus.corpus_char(ch);
+        let constraints = vec![
+            Constraint::Region {
+                chars: vec![c('z'), c('x'), c('c'), c('v')],
+                positions: (0..10).map(|col| col * 3 + 2).collect(),
+            },
+            Constraint::SameGroup {
+                chars: vec![c(','), c('.')],
+                groups: vec![(0..15).collect(), (15..30).collect()],
+            },
+        ];
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+
+        let mut optimizer = AnnealingOptimizer::new(4, 1000)
+            .constrain(constraints.clone())
+            .seed(2);
+        let mut broken = qwerty.clone();
+        broken.swap(&Swap::new(2, 0));
+        assert!(matches!(
+            optimizer.setup(broken),
+            Err(SetupError::Constraint(_))
+        ));
+
+        optimizer
+            .setup(qwerty.clone())
+            .expect("qwerty satisfies the constraints");
+        for (layout, _) in optimizer.run(&analyzer, &objective) {
+            assert!(constraint::check(&constraints, &layout).is_ok());
+        }
+
+        // with no allowed swaps, the run ends without iterating
+        let fixed = qwerty
+            .0
+            .iter()
+            .enumerate()
+            .map(|(pos, c)| Constraint::Region {
+                chars: vec![*c],
+                positions: vec![pos],
+            })
+            .collect();
+        let mut optimizer = AnnealingOptimizer::new(1, u64::MAX).constrain(fixed);
+        optimizer.setup(qwerty.clone()).expect("qwerty is fixed");
+        assert_eq!(qwerty.0, optimizer.run(&analyzer, &objective)[0].0 .0);
+    }
+    /// The acceptance rule used before Metropolis annealing: any swap
+    /// is accepted with probability `temp`, which falls linearly from
+    /// 1 to 0.
+    fn legacy_anneal(
+        analyzer: &Analyzer,
+        objective: &WeightsObjective,
+        layout: Layout,
+        iterations: u64,
+        seed: u64,
+    ) -> f32 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut state = LayoutState::new(analyzer, layout);
+        let swaps: Vec<Swap> = (0..30)
+            .flat_map(|a| (0..30).map(move |b| Swap::new(a, b)))
+            .collect();
+        let mut temp: f64 = 1.0;
+        while temp >= 0.0 {
+            let swap = swaps.choose(&mut rng).expect("swaps is not empty");
+            let diff = objective.score(state.swap_diff(analyzer, swap));
+            if diff < 0.0 || rng.gen::<f64>() < temp {
+                state.swap(analyzer, swap);
+                state.commit();
+            }
+            temp -= 1.0 / iterations as f64;
+        }
+        objective.score(state.stats())
+    }
+    #[test]
+    fn test_metropolis_acceptance() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        let (population, iterations) = (8, 2000);
+        let mean = |scores: &[f32]| scores.iter().sum::<f32>() / scores.len() as f32;
+
+        let legacy: Vec<f32> = (0..population)
+            .map(|seed| {
+                legacy_anneal(
+                    &analyzer,
+                    &objective,
+                    qwerty.clone(),
+                    iterations,
+                    seed as u64,
+                )
+            })
+            .collect();
+        for schedule in [
+            Schedule::default(),
+            Schedule::Adaptive {
+                final_ratio: 1e-3,
+                patience: 500,
+                reheat: 1.5,
+            },
+        ] {
+            let mut optimizer = AnnealingOptimizer::new(population, iterations)
+                .with_schedule(schedule)
+                .with_acceptance(0.8)
+                .seed(3);
+            optimizer.setup(qwerty.clone()).expect("no constraints");
+            let scores: Vec<f32> = optimizer
+                .run(&analyzer, &objective)
+                .into_iter()
+                .map(|(_, score)| score)
+                .collect();
+            assert!(
+                mean(&scores) < mean(&legacy),
+                "{schedule:?} averaged {}, legacy averaged {}",
+                mean(&scores),
+                mean(&legacy)
+            );
+        }
+        for acceptance in [0.0, 1.0, f64::NAN] {
+            assert!(matches!(
+                AnnealingOptimizer::new(1, 1).try_with_acceptance(acceptance),
+                Err(Error::Acceptance(a)) if a.to_bits() == acceptance.to_bits()
+            ));
+        }
+    }
+    #[test]
+    fn test_restarts() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        // without iterations, every member ends where it started
+        let mut optimizer = AnnealingOptimizer::new(4, 0);
+        optimizer.setup(qwerty.clone()).expect("no constraints");
+        let converged = optimizer.run(&analyzer, &objective);
+        assert!(converged.iter().all(|(l, _)| l.0 == qwerty.0));
+
+        let mut optimizer = AnnealingOptimizer::new(4, 0)
+            .with_init(Init::Shuffle)
+            .with_restarts(1)
+            .seed(5);
+        optimizer.setup(qwerty.clone()).expect("no constraints");
+        // start converged, with restarts shuffling
+        optimizer.layouts = vec![qwerty.clone(); 4];
+        let restarted = optimizer.run(&analyzer, &objective);
+        let distinct = restarted
+            .iter()
+            .enumerate()
+            .filter(|(i, (l, _))| restarted[..*i].iter().all(|(o, _)| o.0 != l.0))
+            .count();
+        assert_eq!(4, distinct, "converged members should be restarted");
+    }
+}
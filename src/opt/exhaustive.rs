@@ -0,0 +1,449 @@
+use super::{check_results, check_scores, Objective, Optimizer, Progress, RunControl, SetupError};
+use crate::constraint::{self, Constraint};
+use crate::{analysis::Analyzer, Corpus, CorpusChar, Error, Layout, NgramType, Nstroke, Pos};
+use std::collections::HashMap;
+
+/// An `Optimizer` that finds the best layout by trying every
+/// arrangement of the characters on the unpinned positions, so it's
+/// only feasible for small problems, e.g. placing a few punctuation
+/// keys with everything else pinned.
+///
+/// If the objective is linear in the stroke metrics, as reported by
+/// `Objective::linear_weights`, partial layouts are pruned once the
+/// strokes they complete make them worse than the best layout found.
+/// Otherwise, every arrangement is evaluated.
+///
+/// Progress is reported every `interval` full arrangements reached.
+pub struct ExhaustiveOptimizer {
+    layout: Option<Layout>,
+    pins: Vec<usize>,
+    constraints: Vec<Constraint>,
+    control: RunControl,
+    /// The most arrangements `setup` accepts.
+    pub limit: u64,
+}
+
+impl Default for ExhaustiveOptimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExhaustiveOptimizer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            layout: None,
+            pins: vec![],
+            constraints: vec![],
+            control: RunControl::default(),
+            limit: 10_000_000,
+        }
+    }
+    #[must_use]
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+    fn free_positions(&self, len: usize) -> Vec<Pos> {
+        (0..len).filter(|p| !self.pins.contains(p)).collect()
+    }
+}
+
+/// The number of distinct arrangements of `chars`, saturating at
+/// `u64::MAX`.
+fn arrangements(chars: &[CorpusChar]) -> u64 {
+    let mut counts: HashMap<CorpusChar, u128> = HashMap::new();
+    for c in chars {
+        *counts.entry(*c).or_default() += 1;
+    }
+    // the product of choosing the positions of each character in turn
+    let mut left = chars.len() as u128;
+    let mut total: Option<u128> = Some(1);
+    for count in counts.into_values() {
+        let mut binomial: Option<u128> = Some(1);
+        for i in 0..count {
+            binomial = binomial
+                .and_then(|b| b.checked_mul(left - i))
+                .map(|b| b / (i + 1));
+        }
+        total = total.zip(binomial).and_then(|(t, b)| t.checked_mul(b));
+        left -= count;
+    }
+    total.map_or(u64::MAX, |t| u64::try_from(t).unwrap_or(u64::MAX))
+}
+
+/// The highest frequency in each of the `Corpus` tables, found once so
+/// that bounding every term doesn't scan the tables again.
+struct MaxFrequencies {
+    chars: u32,
+    bigrams: u32,
+    skipgrams: u32,
+    trigrams: u32,
+}
+
+impl MaxFrequencies {
+    fn new(corpus: &Corpus) -> Self {
+        let max = |table: &[u32]| table.iter().max().copied().unwrap_or(0);
+        Self {
+            chars: max(&corpus.chars),
+            bigrams: max(&corpus.bigrams),
+            skipgrams: max(&corpus.skipgrams),
+            trigrams: max(&corpus.trigrams),
+        }
+    }
+}
+
+/// A stroke frequency weighted by a linear objective.
+struct Term {
+    nstroke: Nstroke,
+    skipgram: bool,
+    weight: f32,
+}
+
+impl Term {
+    fn value(&self, corpus: &Corpus, l: &Layout) -> f32 {
+        let ng = self.skipgram.then_some(NgramType::Skipgram);
+        l.frequency(corpus, &self.nstroke, ng) as f32 * self.weight
+    }
+    /// The lowest value the term can take on any layout.
+    fn lower_bound(&self, max: &MaxFrequencies) -> f32 {
+        let max = match (&self.nstroke, self.skipgram) {
+            (Nstroke::Monostroke(_), _) => max.chars,
+            (Nstroke::Bistroke(_), false) => max.bigrams,
+            (Nstroke::Bistroke(_), true) => max.skipgrams,
+            (Nstroke::Tristroke(_), _) => max.trigrams,
+        };
+        (max as f32 * self.weight).min(0.0)
+    }
+}
+
+/// The parts of a linear objective that change as positions are
+/// filled in, by the depth at which they're completed.
+struct Bound {
+    terms: Vec<Vec<Term>>,
+    /// The weighted affinities of each free position.
+    affinities: Vec<Vec<(CorpusChar, f32)>>,
+    /// A lower bound of everything completed at each depth or later.
+    remaining: Vec<f32>,
+}
+
+impl Bound {
+    /// Returns `None` if `objective` doesn't report linear weights, or
+    /// weights any of the aggregate metrics.
+    fn new(
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        free: &[Pos],
+    ) -> Option<Self> {
+        let data = &analyzer.data;
+        let mut weights = objective.linear_weights()?;
+        if weights.len() < data.stat_count() {
+            weights.resize(data.stat_count(), 0.0);
+        }
+        if weights[data.metrics.len()..].iter().any(|w| *w != 0.0) {
+            return None;
+        }
+
+        let depth_of: HashMap<Pos, usize> = free.iter().enumerate().map(|(d, p)| (*p, d)).collect();
+        let mut terms: Vec<Vec<Term>> = free.iter().map(|_| vec![]).collect();
+        for stroke in &data.strokes {
+            let Some(depth) = stroke
+                .nstroke
+                .to_vec()
+                .iter()
+                .filter_map(|p| depth_of.get(p).copied())
+                .max()
+            else {
+                continue;
+            };
+            let mut weight = [0.0; 2];
+            for amount in &stroke.amounts {
+                let skipgram = data.metrics[amount.metric] == NgramType::Skipgram;
+                weight[usize::from(skipgram)] += amount.amount * weights[amount.metric];
+            }
+            let bistroke = matches!(stroke.nstroke, Nstroke::Bistroke(_));
+            for (skipgram, weight) in [(false, weight[0]), (true, weight[1])] {
+                if weight != 0.0 && (bistroke || !skipgram) {
+                    terms[depth].push(Term {
+                        nstroke: stroke.nstroke.clone(),
+                        skipgram,
+                        weight,
+                    });
+                }
+            }
+        }
+        let affinities: Vec<Vec<(CorpusChar, f32)>> = free
+            .iter()
+            .map(|p| {
+                analyzer
+                    .position_affinities
+                    .get(*p)
+                    .map_or(vec![], |indices| {
+                        indices
+                            .iter()
+                            .map(|i| {
+                                let affinity = &data.affinities[*i];
+                                let weight = affinity
+                                    .amounts
+                                    .iter()
+                                    .map(|a| a.amount * weights[a.metric])
+                                    .sum();
+                                (affinity.char, weight)
+                            })
+                            .collect()
+                    })
+            })
+            .collect();
+
+        let max = MaxFrequencies::new(&analyzer.corpus);
+        let mut remaining = vec![0.0; free.len() + 1];
+        for d in (0..free.len()).rev() {
+            let terms_bound: f32 = terms[d].iter().map(|t| t.lower_bound(&max)).sum();
+            let affinity_bound: f32 = affinities[d].iter().map(|(_, w)| w.min(0.0)).sum();
+            remaining[d] = remaining[d + 1] + terms_bound + affinity_bound;
+        }
+        Some(Self {
+            terms,
+            affinities,
+            remaining,
+        })
+    }
+    /// The value of everything completed by placing the character at
+    /// depth `d`.
+    fn completed(&self, corpus: &Corpus, l: &Layout, pos: Pos, d: usize) -> f32 {
+        let terms: f32 = self.terms[d].iter().map(|t| t.value(corpus, l)).sum();
+        let affinities: f32 = self.affinities[d]
+            .iter()
+            .filter(|(c, _)| *c == l.0[pos])
+            .map(|(_, w)| w)
+            .sum();
+        terms + affinities
+    }
+}
+
+struct Search<'a> {
+    analyzer: &'a Analyzer,
+    objective: &'a (dyn Objective + Send + Sync),
+    constraints: &'a [Constraint],
+    control: &'a RunControl,
+    free: Vec<Pos>,
+    bound: Option<Bound>,
+    best: Option<(Layout, f32)>,
+    /// The number of full arrangements reached.
+    visited: u64,
+}
+
+impl Search<'_> {
+    /// Tries every arrangement of the characters on `free[depth..]`.
+    /// `partial` is the value of everything completed so far when
+    /// bounding.
+    fn visit(&mut self, l: &mut Layout, depth: usize, partial: f32) {
+        if self.control.should_stop(self.visited) {
+            return;
+        }
+        if depth == self.free.len() {
+            self.visited += 1;
+            if self.constraints.is_empty() || constraint::check(self.constraints, l).is_ok() {
+                let value = match self.bound {
+                    Some(_) => partial,
+                    None => self.objective.score(&self.analyzer.calc_stats(l)),
+                };
+                if self.best.as_ref().map_or(true, |(_, best)| value < *best) {
+                    self.best = Some((l.clone(), value));
+                }
+            }
+            self.report();
+            return;
+        }
+        if let (Some(bound), Some((_, best))) = (&self.bound, &self.best) {
+            if partial + bound.remaining[depth] >= *best {
+                return;
+            }
+        }
+        let pos = self.free[depth];
+        let mut tried = Vec::with_capacity(self.free.len() - depth);
+        for j in depth..self.free.len() {
+            let other = self.free[j];
+            // repeated characters would give the same arrangements
+            if tried.contains(&l.0[other]) {
+                continue;
+            }
+            tried.push(l.0[other]);
+            l.0.swap(pos, other);
+            let completed = self
+                .bound
+                .as_ref()
+                .map_or(0.0, |b| b.completed(&self.analyzer.corpus, l, pos, depth));
+            self.visit(l, depth + 1, partial + completed);
+            l.0.swap(pos, other);
+        }
+    }
+    fn report(&self) {
+        if let Some((best, _)) = &self.best {
+            if self.control.should_report(self.visited) {
+                self.control.report(&Progress {
+                    member: 0,
+                    iteration: self.visited,
+                    best_score: self.objective.score(&self.analyzer.calc_stats(best)),
+                    best_layout: best,
+                });
+            }
+        }
+    }
+}
+
+impl Optimizer for ExhaustiveOptimizer {
+    /// Fails if there are more than `limit` arrangements of the
+    /// unpinned characters.
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError> {
+        constraint::check(&self.constraints, &l)?;
+        let chars: Vec<CorpusChar> = self
+            .free_positions(l.0.len())
+            .iter()
+            .map(|p| l.0[*p])
+            .collect();
+        let size = arrangements(&chars);
+        if size > self.limit {
+            return Err(SetupError::SearchTooLarge {
+                size,
+                limit: self.limit,
+            });
+        }
+        self.layout = Some(l);
+        Ok(())
+    }
+
+    fn pin(mut self, pins: Vec<usize>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    fn constrain(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    fn control(mut self, control: RunControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)> {
+        let Some(mut l) = self.layout.clone() else {
+            return vec![];
+        };
+        let free = self.free_positions(l.0.len());
+        let mut search = Search {
+            analyzer,
+            objective,
+            constraints: &self.constraints,
+            control: &self.control,
+            bound: Bound::new(analyzer, objective, &free),
+            free,
+            best: None,
+            visited: 0,
+        };
+        search.visit(&mut l, 0, 0.0);
+        let Some((best, _)) = search.best else {
+            return vec![];
+        };
+        let score = objective.score(&analyzer.calc_stats(&best));
+        self.layout = Some(best.clone());
+        vec![(best, score)]
+    }
+
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_scores(analyzer, objective, self.layout.as_slice())?;
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::{
+        tests::setup_analyzer, AnonymousObjective, GreedyOptimizer, Strategy, Weight,
+        WeightsObjective,
+    };
+    #[test]
+    fn test_exhaustive() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        // e, t, h, o, a, n and r
+        let free = [6, 12, 16, 25, 1, 17, 9];
+        let pins: Vec<Pos> = (0..30).filter(|p| !free.contains(p)).collect();
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+
+        let mut optimizer = ExhaustiveOptimizer::new().pin(pins.clone());
+        optimizer
+            .setup(qwerty.clone())
+            .expect("7! is below the limit");
+        let (optimum, score) = optimizer.run(&analyzer, &objective).remove(0);
+        assert!(pins.iter().all(|p| optimum.0[*p] == qwerty.0[*p]));
+
+        let mut greedy = GreedyOptimizer::new(Strategy::Steepest).pin(pins.clone());
+        greedy.setup(qwerty.clone()).expect("no constraints");
+        assert!(score <= greedy.run(&analyzer, &objective)[0].1);
+
+        // objectives without linear weights can't be bounded, but have
+        // the same optimum
+        let squared = AnonymousObjective {
+            function: |stats| stats[0] * stats[0],
+        };
+        let mut optimizer = ExhaustiveOptimizer::new().pin(pins.clone());
+        optimizer
+            .setup(qwerty.clone())
+            .expect("7! is below the limit");
+        assert!(Bound::new(&analyzer, &objective, &free).is_some());
+        assert!(Bound::new(&analyzer, &squared, &free).is_none());
+        let (_, squared_score) = optimizer.run(&analyzer, &squared).remove(0);
+        assert_eq!(score * score, squared_score);
+
+        // bounding cuts off part of the search, finding the same optimum
+        let free: Vec<Pos> = (0..30).filter(|p| !pins.contains(p)).collect();
+        let control = RunControl::default();
+        let search = |bound| {
+            let mut search = Search {
+                analyzer: &analyzer,
+                objective: &objective,
+                constraints: &[],
+                control: &control,
+                free: free.clone(),
+                bound,
+                best: None,
+                visited: 0,
+            };
+            search.visit(&mut qwerty.clone(), 0, 0.0);
+            (search.visited, search.best.map(|(l, _)| l.0))
+        };
+        let (plain, plain_best) = search(None);
+        let (pruned, pruned_best) = search(Bound::new(&analyzer, &objective, &free));
+        assert_eq!(5040, plain);
+        assert!(pruned < plain, "bounding visited {pruned} of {plain}");
+        assert_eq!(plain_best, pruned_best);
+
+        let mut optimizer = ExhaustiveOptimizer::new().with_limit(1000).pin(pins);
+        assert!(matches!(
+            optimizer.setup(qwerty),
+            Err(SetupError::SearchTooLarge {
+                size: 5040,
+                limit: 1000
+            })
+        ));
+        assert_eq!(5040 / 2 / 6, arrangements(&[0, 1, 1, 2, 2, 2, 3]));
+    }
+}
@@ -0,0 +1,443 @@
+use super::Objective;
+use crate::analysis::MetricIndex;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// An objective parsed from a formula, such as one loaded from a
+/// config file. For example, `sfb*10 + max(0, lsb-2)` weights SFBs by
+/// 10 and penalizes LSBs above 2.
+///
+/// Formulas support numbers, metric names, `+`, `-`, `*`, `/`, `^`,
+/// parentheses, and the functions `abs(x)`, `min(x, ...)` and
+/// `max(x, ...)`.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    root: Node,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Abs,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f32),
+    Metric(MetricIndex),
+    Neg(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn eval(&self, stats: &[f32]) -> f32 {
+        match self {
+            Node::Number(n) => *n,
+            Node::Metric(m) => stats[*m],
+            Node::Neg(a) => -a.eval(stats),
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.eval(stats), b.eval(stats));
+                match op {
+                    Operator::Add => a + b,
+                    Operator::Sub => a - b,
+                    Operator::Mul => a * b,
+                    Operator::Div => a / b,
+                    Operator::Pow => a.powf(b),
+                }
+            }
+            Node::Call(f, args) => {
+                let mut values = args.iter().map(|a| a.eval(stats));
+                match f {
+                    Function::Abs => values.next().unwrap_or_default().abs(),
+                    Function::Min => values.fold(f32::INFINITY, f32::min),
+                    Function::Max => values.fold(f32::NEG_INFINITY, f32::max),
+                }
+            }
+        }
+    }
+}
+
+/// Returned when a formula can't be parsed into an `Expression`.
+/// Positions are byte offsets into the formula.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpressionError {
+    /// A character or token that doesn't fit the grammar.
+    Unexpected { position: usize, found: char },
+    /// The formula ended in the middle of an expression.
+    UnexpectedEnd,
+    /// A name that isn't one of the metric names.
+    UnknownMetric { position: usize, name: String },
+    /// A call to a function that doesn't exist.
+    UnknownFunction { position: usize, name: String },
+    /// A function called with the wrong number of arguments.
+    Arguments {
+        position: usize,
+        name: String,
+        count: usize,
+    },
+    /// A metric name whose index is past the end of the stats.
+    MetricOutOfRange {
+        position: usize,
+        name: String,
+        len: usize,
+    },
+    /// The formula nests deeper than `Expression::MAX_DEPTH`.
+    TooDeep { position: usize },
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::Unexpected { position, found } => {
+                write!(f, "unexpected '{found}' at {position}")
+            }
+            ExpressionError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ExpressionError::UnknownMetric { position, name } => {
+                write!(f, "unknown metric '{name}' at {position}")
+            }
+            ExpressionError::UnknownFunction { position, name } => {
+                write!(f, "unknown function '{name}' at {position}")
+            }
+            ExpressionError::Arguments {
+                position,
+                name,
+                count,
+            } => write!(
+                f,
+                "wrong number of arguments to '{name}' at {position}: {count}"
+            ),
+            ExpressionError::MetricOutOfRange {
+                position,
+                name,
+                len,
+            } => write!(
+                f,
+                "metric '{name}' at {position} is out of range for {len} stats"
+            ),
+            ExpressionError::TooDeep { position } => {
+                write!(f, "expression nests too deeply at {position}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// A recursive descent parser over the characters of a formula.
+struct Parser<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    names: &'a [&'a str],
+    stat_count: usize,
+    /// How deeply the node being parsed is nested in the tree.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn position(&mut self) -> usize {
+        self.chars.peek().map_or(self.source.len(), |(i, _)| *i)
+    }
+    /// Goes one level deeper into the tree, failing past
+    /// `Expression::MAX_DEPTH`.
+    fn descend(&mut self) -> Result<(), ExpressionError> {
+        self.depth += 1;
+        if self.depth > Expression::MAX_DEPTH {
+            return Err(ExpressionError::TooDeep {
+                position: self.position(),
+            });
+        }
+        Ok(())
+    }
+    fn peek(&mut self) -> Option<(usize, char)> {
+        while let Some((_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+        self.chars.peek().copied()
+    }
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek().is_some_and(|(_, next)| next == c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, c: char) -> Result<(), ExpressionError> {
+        match self.peek() {
+            Some((_, next)) if next == c => {
+                self.chars.next();
+                Ok(())
+            }
+            Some((position, found)) => Err(ExpressionError::Unexpected { position, found }),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+    /// Takes characters while `f` holds, returning where they start and
+    /// end.
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> (usize, usize) {
+        let start = self.position();
+        let mut end = start;
+        while let Some((i, c)) = self.chars.peek().copied() {
+            if !f(c) {
+                break;
+            }
+            end = i + c.len_utf8();
+            self.chars.next();
+        }
+        (start, end)
+    }
+    fn sum(&mut self) -> Result<Node, ExpressionError> {
+        let depth = self.depth;
+        let mut node = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                Operator::Add
+            } else if self.eat('-') {
+                Operator::Sub
+            } else {
+                self.depth = depth;
+                return Ok(node);
+            };
+            // each operator nests the terms before it one level deeper
+            self.descend()?;
+            node = Node::Binary(op, Box::new(node), Box::new(self.product()?));
+        }
+    }
+    fn product(&mut self) -> Result<Node, ExpressionError> {
+        let depth = self.depth;
+        let mut node = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Operator::Mul
+            } else if self.eat('/') {
+                Operator::Div
+            } else {
+                self.depth = depth;
+                return Ok(node);
+            };
+            self.descend()?;
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        let depth = self.depth;
+        self.descend()?;
+        let node = if self.eat('-') {
+            Node::Neg(Box::new(self.unary()?))
+        } else {
+            let base = self.atom()?;
+            if self.eat('^') {
+                // right associative, binding tighter than negation on the
+                // left
+                Node::Binary(Operator::Pow, Box::new(base), Box::new(self.unary()?))
+            } else {
+                base
+            }
+        };
+        self.depth = depth;
+        Ok(node)
+    }
+    fn atom(&mut self) -> Result<Node, ExpressionError> {
+        let Some((position, c)) = self.peek() else {
+            return Err(ExpressionError::UnexpectedEnd);
+        };
+        if c == '(' {
+            self.chars.next();
+            let node = self.sum()?;
+            self.expect(')')?;
+            Ok(node)
+        } else if c.is_ascii_digit() || c == '.' {
+            let (start, end) = self.take_while(|c| c.is_ascii_digit() || c == '.');
+            self.source[start..end]
+                .parse()
+                .map(Node::Number)
+                .map_err(|_| ExpressionError::Unexpected { position, found: c })
+        } else if c.is_alphabetic() || c == '_' {
+            let (start, end) = self.take_while(|c| c.is_alphanumeric() || c == '_');
+            let name = &self.source[start..end];
+            if self.eat('(') {
+                self.call(position, name)
+            } else {
+                match self.names.iter().position(|n| *n == name) {
+                    Some(m) if m < self.stat_count => Ok(Node::Metric(m)),
+                    Some(_) => Err(ExpressionError::MetricOutOfRange {
+                        position,
+                        name: name.to_string(),
+                        len: self.stat_count,
+                    }),
+                    None => Err(ExpressionError::UnknownMetric {
+                        position,
+                        name: name.to_string(),
+                    }),
+                }
+            }
+        } else {
+            Err(ExpressionError::Unexpected { position, found: c })
+        }
+    }
+    /// Parses the arguments of a call to `name`, after its opening
+    /// parenthesis.
+    fn call(&mut self, position: usize, name: &str) -> Result<Node, ExpressionError> {
+        let function = match name {
+            "abs" => Function::Abs,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            _ => {
+                return Err(ExpressionError::UnknownFunction {
+                    position,
+                    name: name.to_string(),
+                })
+            }
+        };
+        let mut args = vec![];
+        if !self.eat(')') {
+            loop {
+                args.push(self.sum()?);
+                if self.eat(')') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+        let valid = match function {
+            Function::Abs => args.len() == 1,
+            Function::Min | Function::Max => !args.is_empty(),
+        };
+        if !valid {
+            return Err(ExpressionError::Arguments {
+                position,
+                name: name.to_string(),
+                count: args.len(),
+            });
+        }
+        Ok(Node::Call(function, args))
+    }
+}
+
+impl Expression {
+    /// How deeply a formula may nest, e.g. through parentheses or a
+    /// long chain of operators. Formulas may come from untrusted config
+    /// files, and both parsing and scoring recurse through the tree.
+    pub const MAX_DEPTH: usize = 256;
+
+    /// Parses `source`, where each name in `names` refers to the
+    /// metric at its index. Fails if a name used in `source` is past
+    /// `stat_count`, the number of stats the expression will score,
+    /// e.g. from `MetricData::stat_count`.
+    pub fn parse(source: &str, names: &[&str], stat_count: usize) -> Result<Self, ExpressionError> {
+        let mut parser = Parser {
+            source,
+            chars: source.char_indices().peekable(),
+            names,
+            stat_count,
+            depth: 0,
+        };
+        let root = parser.sum()?;
+        match parser.peek() {
+            Some((position, found)) => Err(ExpressionError::Unexpected { position, found }),
+            None => Ok(Self { root }),
+        }
+    }
+}
+
+impl Objective for Expression {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.root.eval(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_expression() {
+        let names = ["sfb", "lsb", "rolls"];
+        let stats = [0.02, 3.0, 0.5];
+        let score = |source: &str| {
+            Expression::parse(source, &names, stats.len())
+                .expect("expression should parse")
+                .score(&stats)
+        };
+        assert!((score("sfb*10 + max(0, lsb-2)") - 1.2).abs() < 1e-5);
+        assert_eq!(7.0, score("1 + 2 * 3"));
+        assert_eq!(9.0, score("(1 + 2) * 3"));
+        assert_eq!(-6.0, score("-lsb / rolls"));
+        assert_eq!(512.0, score("2 ^ 3 ^ 2"));
+        assert_eq!(-9.0, score("-lsb^2"));
+        assert_eq!(3.0, score("abs(rolls - 3.5)"));
+        assert_eq!(0.02, score("min(lsb, sfb, rolls)"));
+
+        let error = |source: &str| Expression::parse(source, &names, stats.len()).unwrap_err();
+        assert_eq!(
+            ExpressionError::UnknownMetric {
+                position: 6,
+                name: "sfs".to_string()
+            },
+            error("sfb + sfs")
+        );
+        assert_eq!(
+            ExpressionError::UnknownFunction {
+                position: 0,
+                name: "sqrt".to_string()
+            },
+            error("sqrt(sfb)")
+        );
+        assert_eq!(
+            ExpressionError::Arguments {
+                position: 0,
+                name: "abs".to_string(),
+                count: 2
+            },
+            error("abs(sfb, lsb)")
+        );
+        assert_eq!(ExpressionError::UnexpectedEnd, error("sfb *"));
+        assert_eq!(ExpressionError::UnexpectedEnd, error("max(sfb"));
+        assert_eq!(
+            ExpressionError::Unexpected {
+                position: 4,
+                found: ')'
+            },
+            error("sfb ) + 1")
+        );
+        assert_eq!(
+            ExpressionError::Unexpected {
+                position: 0,
+                found: '1'
+            },
+            error("1.2.3")
+        );
+        assert_eq!(
+            Err(ExpressionError::MetricOutOfRange {
+                position: 6,
+                name: "rolls".to_string(),
+                len: 2
+            }),
+            Expression::parse("sfb + rolls", &names, 2).map(|_| ())
+        );
+        let nested = "(".repeat(1000) + "sfb" + &")".repeat(1000);
+        assert!(matches!(error(&nested), ExpressionError::TooDeep { .. }));
+        assert!(matches!(
+            error(&"-".repeat(100_000)),
+            ExpressionError::TooDeep { .. }
+        ));
+        let chain = vec!["sfb"; 100_000].join("+");
+        assert!(matches!(error(&chain), ExpressionError::TooDeep { .. }));
+        let chain = vec!["sfb"; 100].join("+");
+        assert!((score(&chain) - 2.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,399 @@
+use super::{
+    by_score, check_results, check_scores, seeded_rng, Init, Objective, Optimizer, Progress,
+    RunControl, SetupError,
+};
+use crate::constraint::{self, Constraint};
+use crate::{analysis::Analyzer, CorpusChar, Error, Layout, Pos, Swap};
+use rand::prelude::*;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// How a `GeneticOptimizer` combines two parents into a child. Both
+/// operators keep the child a permutation of its parents.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Crossover {
+    /// Order crossover (OX): copies a random segment from the first
+    /// parent, then fills the rest in the order the remaining
+    /// characters appear in the second parent.
+    #[default]
+    Order,
+    /// Partially mapped crossover (PMX): copies a random segment from
+    /// the first parent, and keeps the other characters where the
+    /// second parent has them when possible.
+    PartiallyMapped,
+}
+
+/// A population-based `Optimizer`. Each generation, parents are picked
+/// by tournament selection and combined with `crossover`, and the
+/// children are mutated with random swaps. Children are evaluated in
+/// parallel.
+///
+/// Crossover and mutation only move characters between unpinned
+/// positions. Children breaking the constraints are replaced by a copy
+/// of their fitter parent.
+///
+/// Members don't persist across generations, so progress is reported
+/// for the whole population after each reported generation, with
+/// `Progress::member` being the rank of each layout.
+pub struct GeneticOptimizer {
+    layouts: Vec<Layout>,
+    pins: Vec<usize>,
+    constraints: Vec<Constraint>,
+    control: RunControl,
+    seed: Option<u64>,
+    pub population_size: usize,
+    pub generations: u64,
+    pub crossover: Crossover,
+    /// The probability of a child being mutated with a random swap.
+    pub mutation_rate: f64,
+    /// The number of layouts competing in each tournament selection.
+    pub tournament_size: usize,
+    /// The number of best layouts carried over unchanged to the next
+    /// generation.
+    pub elitism: usize,
+    /// How the starting population is created.
+    pub init: Init,
+}
+
+impl GeneticOptimizer {
+    #[must_use]
+    pub fn new(population_size: usize, generations: u64) -> Self {
+        Self {
+            layouts: Vec::with_capacity(population_size),
+            pins: vec![],
+            constraints: vec![],
+            control: RunControl::default(),
+            seed: None,
+            population_size,
+            generations,
+            crossover: Crossover::default(),
+            mutation_rate: 0.2,
+            tournament_size: 3,
+            elitism: 2,
+            init: Init::Shuffle,
+        }
+    }
+    #[must_use]
+    pub fn with_init(mut self, init: Init) -> Self {
+        self.init = init;
+        self
+    }
+    #[must_use]
+    pub fn with_crossover(mut self, crossover: Crossover) -> Self {
+        self.crossover = crossover;
+        self
+    }
+    fn free_positions(&self, len: usize) -> Vec<Pos> {
+        (0..len).filter(|p| !self.pins.contains(p)).collect()
+    }
+    /// Picks the best of `tournament_size` random members of
+    /// `population`.
+    fn select<'a>(&self, population: &'a [(Layout, f32)], rng: &mut impl Rng) -> &'a (Layout, f32) {
+        population
+            .choose_multiple(rng, self.tournament_size.max(1))
+            .min_by(|a, b| by_score(a.1, b.1))
+            .expect("population should not be empty")
+    }
+}
+
+/// The variation operators of the evolutionary optimizers, which only
+/// move characters between the `free` positions.
+pub(super) struct Variation<'a> {
+    pub crossover: Crossover,
+    pub mutation_rate: f64,
+    pub constraints: &'a [Constraint],
+    pub free: &'a [Pos],
+}
+
+impl Variation<'_> {
+    /// Swaps two random free positions of `l`, unless that breaks the
+    /// constraints.
+    fn mutate(&self, l: &mut Layout, rng: &mut impl Rng) {
+        if let [a, b] = self.free.choose_multiple(rng, 2).collect::<Vec<_>>()[..] {
+            let swap = Swap::new(*a, *b);
+            if constraint::allows_swap(self.constraints, l, &swap) {
+                l.swap(&swap);
+            }
+        }
+    }
+    /// Creates a child of `a` and `b`, which must share the characters
+    /// on the free positions.
+    pub(super) fn breed(&self, a: &Layout, b: &Layout, rng: &mut impl Rng) -> Layout {
+        let free = self.free;
+        let chars_a: Vec<CorpusChar> = free.iter().map(|p| a.0[*p]).collect();
+        let chars_b: Vec<CorpusChar> = free.iter().map(|p| b.0[*p]).collect();
+        let mut child = a.clone();
+        if free.len() >= 2 {
+            let mut ends = [rng.gen_range(0..free.len()), rng.gen_range(0..free.len())];
+            ends.sort_unstable();
+            let segment = ends[0]..ends[1] + 1;
+            let chars = crossover(self.crossover, &chars_a, &chars_b, segment);
+            for (p, c) in free.iter().zip(chars) {
+                child.0[*p] = c;
+            }
+        }
+        if rng.gen::<f64>() < self.mutation_rate {
+            self.mutate(&mut child, rng);
+        }
+        child
+    }
+}
+
+/// Applies `kind` to the character sequences `a` and `b`, copying
+/// `segment` from `a`. Repeated characters are told apart by their
+/// order of occurrence, so the sequences are treated as permutations
+/// of the indices of `a`.
+fn crossover(
+    kind: Crossover,
+    a: &[CorpusChar],
+    b: &[CorpusChar],
+    segment: std::ops::Range<usize>,
+) -> Vec<CorpusChar> {
+    let mut occurrences: HashMap<CorpusChar, Vec<usize>> = HashMap::new();
+    for (i, c) in a.iter().enumerate().rev() {
+        occurrences.entry(*c).or_default().push(i);
+    }
+    let b: Vec<usize> = b
+        .iter()
+        .map(|c| {
+            occurrences
+                .get_mut(c)
+                .and_then(Vec::pop)
+                .expect("parents should share the same characters")
+        })
+        .collect();
+
+    let len = a.len();
+    let mut child: Vec<Option<usize>> = vec![None; len];
+    let mut used = vec![false; len];
+    for i in segment.clone() {
+        child[i] = Some(i);
+        used[i] = true;
+    }
+    match kind {
+        Crossover::Order => {
+            let mut values = (0..len).map(|i| b[(segment.end + i) % len]);
+            for i in 0..len {
+                let pos = (segment.end + i) % len;
+                if child[pos].is_none() {
+                    let v = values
+                        .find(|v| !used[*v])
+                        .expect("an unused value should remain");
+                    child[pos] = Some(v);
+                }
+            }
+        }
+        Crossover::PartiallyMapped => {
+            let mut pos_in_b = vec![0; len];
+            for (i, v) in b.iter().enumerate() {
+                pos_in_b[*v] = i;
+            }
+            for i in segment.clone() {
+                let v = b[i];
+                if used[v] {
+                    continue;
+                }
+                // follow the mapping until it leaves the segment. Within
+                // the segment, the child holds index `pos` of `a` at `pos`
+                let mut pos = i;
+                while segment.contains(&pos) {
+                    pos = pos_in_b[pos];
+                }
+                child[pos] = Some(v);
+                used[v] = true;
+            }
+            for (i, v) in child.iter_mut().enumerate() {
+                if v.is_none() {
+                    *v = Some(b[i]);
+                }
+            }
+        }
+    }
+    child
+        .into_iter()
+        .map(|v| a[v.expect("every position should be filled")])
+        .collect()
+}
+
+impl Optimizer for GeneticOptimizer {
+    /// Creates the starting population from `l` with `init`.
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError> {
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        self.layouts = self.init.population(
+            &l,
+            self.population_size,
+            &self.pins,
+            &self.constraints,
+            seed,
+        )?;
+        Ok(())
+    }
+
+    fn pin(mut self, pins: Vec<usize>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    fn constrain(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    fn control(mut self, control: RunControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)> {
+        let evaluate = |l: Layout| {
+            let score = objective.score(&analyzer.calc_stats(&l));
+            (l, score)
+        };
+        let mut population: Vec<(Layout, f32)> = std::mem::take(&mut self.layouts)
+            .into_par_iter()
+            .map(evaluate)
+            .collect();
+        if population.is_empty() {
+            return population;
+        }
+        let free = self.free_positions(population[0].0 .0.len());
+        let variation = Variation {
+            crossover: self.crossover,
+            mutation_rate: self.mutation_rate,
+            constraints: &self.constraints,
+            free: &free,
+        };
+        population.sort_by(|a, b| by_score(a.1, b.1));
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let size = population.len() as u64;
+        for generation in 1..=self.generations {
+            if self.control.should_stop(generation - 1) {
+                break;
+            }
+            let elites = self.elitism.min(population.len());
+            let children: Vec<(Layout, f32)> = (elites..population.len())
+                .into_par_iter()
+                .map(|child| {
+                    let mut rng = seeded_rng(seed, generation * size + child as u64);
+                    let a = self.select(&population, &mut rng);
+                    let b = self.select(&population, &mut rng);
+                    let child = variation.breed(&a.0, &b.0, &mut rng);
+                    if constraint::check(&self.constraints, &child).is_ok() {
+                        evaluate(child)
+                    } else if by_score(a.1, b.1).is_le() {
+                        a.clone()
+                    } else {
+                        b.clone()
+                    }
+                })
+                .collect();
+            population.truncate(elites);
+            population.extend(children);
+            population.sort_by(|a, b| by_score(a.1, b.1));
+            if self.control.should_report(generation) {
+                for (member, (layout, score)) in population.iter().enumerate() {
+                    self.control.report(&Progress {
+                        member,
+                        iteration: generation,
+                        best_score: *score,
+                        best_layout: layout,
+                    });
+                }
+            }
+        }
+        population.sort_by(|a, b| by_score(a.1, b.1));
+        self.layouts = population.iter().map(|(l, _)| l.clone()).collect();
+        population
+    }
+
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_scores(analyzer, objective, &self.layouts)?;
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::{tests::setup_analyzer, Weight, WeightsObjective};
+    #[test]
+    fn test_genetic() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let a = vec![0, 1, 2, 3, 3, 4, 5, 6];
+        for kind in [Crossover::Order, Crossover::PartiallyMapped] {
+            for _ in 0..100 {
+                let mut b = a.clone();
+                b.shuffle(&mut rng);
+                let (start, end) = (rng.gen_range(0..4), rng.gen_range(4..8));
+                let mut child = crossover(kind, &a, &b, start..end);
+                assert_eq!(a[start..end], child[start..end]);
+                child.sort_unstable();
+                assert_eq!(a, child, "{kind:?} should keep the characters");
+            }
+        }
+        // positions outside the segment keep b's characters when
+        // they aren't in the segment
+        let child = crossover(
+            Crossover::PartiallyMapped,
+            &[0, 1, 2, 3, 4, 5],
+            &[5, 4, 3, 2, 1, 0],
+            1..3,
+        );
+        assert_eq!(vec![5, 1, 2, 3, 4, 0], child);
+
+        let analyzer = setup_analyzer();
+        let corpus = &analyzer.corpus;
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let constraints = vec![Constraint::SameGroup {
+            chars: vec![corpus.corpus_char(','), corpus.corpus_char('.')],
+            groups: vec![(0..15).collect(), (15..30).collect()],
+        }];
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        let start = objective.score(&analyzer.calc_stats(&qwerty));
+        let mut sorted_qwerty = qwerty.0.clone();
+        sorted_qwerty.sort_unstable();
+
+        let run = |kind| {
+            let mut optimizer = GeneticOptimizer::new(20, 50)
+                .with_crossover(kind)
+                .pin(vec![0])
+                .constrain(constraints.clone())
+                .seed(3);
+            optimizer
+                .setup(qwerty.clone())
+                .expect("qwerty satisfies the constraints");
+            optimizer.run(&analyzer, &objective)
+        };
+        for kind in [Crossover::Order, Crossover::PartiallyMapped] {
+            let optimized = run(kind);
+            assert_eq!(20, optimized.len());
+            assert!(optimized[0].1 < start, "{kind:?} should improve qwerty");
+            for (layout, _) in &optimized {
+                assert_eq!(qwerty.0[0], layout.0[0]);
+                assert!(constraint::check(&constraints, layout).is_ok());
+                let mut chars = layout.0.clone();
+                chars.sort_unstable();
+                assert_eq!(sorted_qwerty, chars);
+            }
+            for ((a, _), (b, _)) in optimized.iter().zip(run(kind)) {
+                assert_eq!(a.0, b.0, "runs with the same seed should match");
+            }
+        }
+    }
+}
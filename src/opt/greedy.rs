@@ -0,0 +1,258 @@
+use super::{
+    by_score, check_layouts, check_results, check_scores, possible_swaps, Objective, Optimizer,
+    Progress, RunControl, SetupError,
+};
+use crate::constraint::{self, Constraint};
+use crate::{analysis::Analyzer, state::LayoutState, Error, Layout, Swap};
+use rayon::prelude::*;
+
+/// Which improving swap a `GreedyOptimizer` applies at each step.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Strategy {
+    /// Applies the swap that improves the score the most, found by
+    /// calculating the diffs of every swap at once.
+    #[default]
+    Steepest,
+    /// Applies the first improving swap found, continuing the search
+    /// after it on the next step.
+    FirstImprovement,
+}
+
+/// An `Optimizer` that applies improving swaps until there are none
+/// left. Its results are local optima: no single swap allowed by the
+/// pins and constraints improves their score.
+///
+/// Since it never accepts worse layouts, it is best used to polish
+/// the results of another optimizer, e.g. with
+/// `GreedyOptimizer::setup_many`.
+pub struct GreedyOptimizer {
+    layouts: Vec<Layout>,
+    pins: Vec<usize>,
+    constraints: Vec<Constraint>,
+    control: RunControl,
+    pub strategy: Strategy,
+}
+
+impl GreedyOptimizer {
+    #[must_use]
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            layouts: vec![],
+            pins: vec![],
+            constraints: vec![],
+            control: RunControl::default(),
+            strategy,
+        }
+    }
+    /// Like `Optimizer::setup`, but optimizes several layouts, such as
+    /// the ones returned by another optimizer.
+    pub fn setup_many(
+        &mut self,
+        layouts: impl IntoIterator<Item = Layout>,
+    ) -> Result<(), SetupError> {
+        self.layouts = check_layouts(layouts, &self.constraints)?;
+        Ok(())
+    }
+    fn allows(&self, l: &Layout, swap: &Swap) -> bool {
+        !self.pins.iter().any(|p| *p == swap.a || *p == swap.b)
+            && constraint::allows_swap(&self.constraints, l, swap)
+    }
+    /// Applies improving swaps to `state` until it reaches a local
+    /// optimum, or the `RunControl::max_iterations` limit.
+    pub fn descend(
+        &self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        state: &mut LayoutState,
+    ) {
+        self.descend_member(analyzer, objective, 0, state);
+    }
+    /// Like `GreedyOptimizer::descend`, reporting progress as
+    /// population member `member`.
+    fn descend_member(
+        &self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        member: usize,
+        state: &mut LayoutState,
+    ) {
+        let report = |steps: u64, state: &LayoutState| {
+            if self.control.should_report(steps) {
+                self.control.report(&Progress {
+                    member,
+                    iteration: steps,
+                    best_score: objective.score(state.stats()),
+                    best_layout: state.layout(),
+                });
+            }
+        };
+        let mut steps = 0;
+        match self.strategy {
+            Strategy::Steepest => {
+                while !self.control.should_stop(steps) {
+                    let matrix = state.swap_matrix(analyzer);
+                    let best = matrix
+                        .iter()
+                        .filter(|(swap, _)| self.allows(state.layout(), swap))
+                        .map(|(swap, diffs)| {
+                            (swap, objective.score_transition(state.stats(), diffs))
+                        })
+                        .min_by(|a, b| a.1.total_cmp(&b.1));
+                    match best {
+                        Some((swap, diff)) if diff < 0.0 => {
+                            state.apply(analyzer, &swap, matrix.diffs(&swap));
+                            state.commit();
+                            steps += 1;
+                            report(steps, state);
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            Strategy::FirstImprovement => {
+                let swaps = possible_swaps(state.layout().0.len(), &self.pins);
+                let mut diffs = vec![0.0; state.stats().len()];
+                // the number of swaps checked since the last improvement
+                let mut unimproved = 0;
+                let mut i = 0;
+                while unimproved < swaps.len() && !self.control.should_stop(steps) {
+                    let swap = &swaps[i];
+                    i = (i + 1) % swaps.len();
+                    unimproved += 1;
+                    if !constraint::allows_swap(&self.constraints, state.layout(), swap) {
+                        continue;
+                    }
+                    diffs.copy_from_slice(state.swap_diff(analyzer, swap));
+                    if objective.score_transition(state.stats(), &diffs) < 0.0 {
+                        state.apply(analyzer, swap, &diffs);
+                        state.commit();
+                        steps += 1;
+                        report(steps, state);
+                        unimproved = 0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Optimizer for GreedyOptimizer {
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError> {
+        self.setup_many([l])
+    }
+
+    fn pin(mut self, pins: Vec<usize>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    fn constrain(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    fn control(mut self, control: RunControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)> {
+        let mut population = std::mem::take(&mut self.layouts);
+        let mut layouts: Vec<(Layout, f32)> = population
+            .par_iter_mut()
+            .enumerate()
+            .map(|(member, l)| {
+                let mut state = LayoutState::new(analyzer, l.clone());
+                self.descend_member(analyzer, objective, member, &mut state);
+                let score = objective.score(state.stats());
+                *l = state.into_layout();
+                (l.clone(), score)
+            })
+            .collect();
+        self.layouts = population;
+        layouts.sort_by(|a, b| by_score(a.1, b.1));
+        layouts
+    }
+
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_scores(analyzer, objective, &self.layouts)?;
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::{
+        tests::setup_analyzer, AnnealingOptimizer, Metric, Target, Weight, WeightsObjective,
+    };
+    #[test]
+    fn test_greedy() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        let start = objective.score(&analyzer.calc_stats(&qwerty));
+        let is_local_optimum = |l: &Layout| {
+            let state = LayoutState::new(&analyzer, l.clone());
+            state
+                .swap_matrix(&analyzer)
+                .iter()
+                .filter(|(swap, _)| swap.a != 0 && swap.b != 0)
+                .all(|(_, diffs)| objective.score_transition(state.stats(), diffs) >= 0.0)
+        };
+
+        for strategy in [Strategy::Steepest, Strategy::FirstImprovement] {
+            let mut optimizer = GreedyOptimizer::new(strategy).pin(vec![0]);
+            optimizer.setup(qwerty.clone()).expect("no constraints");
+            let (layout, score) = &optimizer.run(&analyzer, &objective)[0];
+            assert!(*score < start, "{strategy:?} should improve qwerty");
+            assert_eq!(qwerty.0[0], layout.0[0]);
+            assert!(is_local_optimum(layout), "{strategy:?} stopped early");
+        }
+
+        // polishing annealed layouts never makes them worse
+        let mut annealing = AnnealingOptimizer::new(4, 200).pin(vec![0]).seed(4);
+        annealing.setup(qwerty.clone()).expect("no constraints");
+        let annealed = annealing.run(&analyzer, &objective);
+        let mut greedy = GreedyOptimizer::new(Strategy::Steepest).pin(vec![0]);
+        greedy
+            .setup_many(annealed.iter().map(|(l, _)| l.clone()))
+            .expect("no constraints");
+        let polished = greedy.run(&analyzer, &objective);
+        assert_eq!(annealed.len(), polished.len());
+        assert!(polished[0].1 <= annealed[0].1);
+        assert!(polished.iter().all(|(l, _)| is_local_optimum(l)));
+
+        // each applied swap counts as an iteration
+        for strategy in [Strategy::Steepest, Strategy::FirstImprovement] {
+            let mut optimizer =
+                GreedyOptimizer::new(strategy).control(RunControl::new().max_iterations(2));
+            optimizer.setup(qwerty.clone()).expect("no constraints");
+            let (layout, _) = &optimizer.run(&analyzer, &objective)[0];
+            assert!(layout.changed_positions(&qwerty).len() <= 4);
+        }
+
+        // non-linear objectives are scored on the stats after each swap,
+        // not on the diffs alone
+        let target = Target::new(Metric(0), start * 0.5);
+        let far = target.score(&analyzer.calc_stats(&qwerty));
+        let mut optimizer = GreedyOptimizer::new(Strategy::Steepest).pin(vec![0]);
+        optimizer.setup(qwerty).expect("no constraints");
+        let (layout, score) = &optimizer.run(&analyzer, &target)[0];
+        assert!(*score < far / 4.0, "{score} should be close to the target");
+        assert!((score - target.score(&analyzer.calc_stats(layout))).abs() < 1e-3);
+    }
+}
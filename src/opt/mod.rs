@@ -0,0 +1,636 @@
+use crate::constraint::{self, Constraint, ConstraintViolation};
+use crate::{analysis::Analyzer, Error, Layout, Pos, Swap};
+use rand::prelude::*;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+mod annealing;
+mod exhaustive;
+mod expression;
+mod genetic;
+mod greedy;
+mod nsga;
+mod objective;
+mod rank;
+mod tabu;
+
+pub use annealing::{AnnealingOptimizer, Schedule};
+pub use exhaustive::ExhaustiveOptimizer;
+pub use expression::{Expression, ExpressionError};
+pub use genetic::{Crossover, GeneticOptimizer};
+pub use greedy::{GreedyOptimizer, Strategy};
+pub use nsga::NsgaOptimizer;
+pub use objective::{
+    AnonymousObjective, BoxedObjective, ClosureObjective, Hinge, Max, Metric, Min, Objective,
+    ObjectiveFn, Product, Ratio, Scaled, Sum, Target, Weight, WeightsObjective,
+};
+pub use rank::{
+    crowding_distances, dedup, dominates, pareto_front, pareto_fronts, Criterion, Sense,
+};
+pub use tabu::TabuOptimizer;
+
+/// Returned when an `Optimizer` can't be set up with a layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupError {
+    /// The starting layout breaks one of the constraints.
+    Constraint(ConstraintViolation),
+    /// An exhaustive search would have to enumerate more than `limit`
+    /// layouts. `size` saturates at `u64::MAX`.
+    SearchTooLarge { size: u64, limit: u64 },
+    /// Starting layout `index` of `Init::Layouts` doesn't have the same
+    /// characters as the layout passed to `setup`.
+    Mismatch { index: usize },
+    /// Starting layout `index` of `Init::Layouts` has a different
+    /// character on pinned position `pos`.
+    Pinned { index: usize, pos: Pos },
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::Constraint(v) => v.fmt(f),
+            SetupError::SearchTooLarge { size, limit } => write!(
+                f,
+                "search space of {size} layouts exceeds the limit of {limit}"
+            ),
+            SetupError::Mismatch { index } => write!(
+                f,
+                "starting layout {index} doesn't have the same characters as the layout"
+            ),
+            SetupError::Pinned { index, pos } => {
+                write!(f, "starting layout {index} moves pinned position {pos}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+impl From<ConstraintViolation> for SetupError {
+    fn from(v: ConstraintViolation) -> Self {
+        SetupError::Constraint(v)
+    }
+}
+
+/// A snapshot of a running optimization, passed to the observer of a
+/// `RunControl`.
+#[derive(Clone, Copy)]
+pub struct Progress<'a> {
+    /// The index of the population member the progress is for.
+    pub member: usize,
+    /// The number of iterations the member has completed.
+    pub iteration: u64,
+    pub best_score: f32,
+    pub best_layout: &'a Layout,
+}
+
+/// Stops running optimizations when cancelled. Clones share the same
+/// state, so a clone can be cancelled from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub type Observer = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// Progress reporting and cancellation for `Optimizer::run`. A
+/// cancelled run stops early and returns the best layouts found so
+/// far.
+#[derive(Default)]
+pub struct RunControl {
+    observer: Option<Observer>,
+    interval: u64,
+    token: CancellationToken,
+    max_iterations: Option<u64>,
+}
+
+impl RunControl {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Calls `observer` every `interval` iterations of each population
+    /// member. Members run in parallel, so it may be called from
+    /// several threads at once.
+    #[must_use]
+    pub fn observe(
+        mut self,
+        interval: u64,
+        observer: impl Fn(&Progress) + Send + Sync + 'static,
+    ) -> Self {
+        self.observer = Some(Box::new(observer));
+        self.interval = interval;
+        self
+    }
+    #[must_use]
+    pub fn cancel_with(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+    /// Stops each population member after `iterations` iterations, on
+    /// top of any limit set on the optimizer. Iterations are swaps for
+    /// greedy, annealing and tabu search, generations for the genetic
+    /// optimizers, and complete arrangements for exhaustive search.
+    #[must_use]
+    pub fn max_iterations(mut self, iterations: u64) -> Self {
+        self.max_iterations = Some(iterations);
+        self
+    }
+    /// The token cancelling runs using this control.
+    #[must_use]
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+    /// Whether a member should stop after `iteration` iterations,
+    /// either because the run was cancelled or it hit the limit.
+    pub(crate) fn should_stop(&self, iteration: u64) -> bool {
+        self.is_cancelled() || self.max_iterations.is_some_and(|max| iteration >= max)
+    }
+    /// Whether progress should be reported after `iteration`
+    /// iterations.
+    pub(crate) fn should_report(&self, iteration: u64) -> bool {
+        self.observer.is_some() && self.interval > 0 && iteration % self.interval == 0
+    }
+    pub(crate) fn report(&self, progress: &Progress) {
+        if let Some(observer) = &self.observer {
+            observer(progress);
+        }
+    }
+}
+
+/// Creates the RNG of `stream` for a run seeded with `seed`. Each
+/// population member gets its own stream, so results don't depend on
+/// which thread runs it.
+pub(crate) fn seeded_rng(seed: u64, stream: u64) -> StdRng {
+    StdRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// Every swap on a layout of `len` positions that doesn't move any of
+/// `pins`.
+pub(crate) fn possible_swaps(len: usize, pins: &[Pos]) -> Vec<Swap> {
+    (0..len)
+        .flat_map(|a| (a + 1..len).map(move |b| Swap::new(a, b)))
+        .filter(|swap| !pins.iter().any(|p| *p == swap.a || *p == swap.b))
+        .collect()
+}
+
+/// Checks that every one of `layouts` satisfies `constraints`, for
+/// optimizers that can be set up with several layouts.
+pub(crate) fn check_layouts(
+    layouts: impl IntoIterator<Item = Layout>,
+    constraints: &[Constraint],
+) -> Result<Vec<Layout>, SetupError> {
+    let layouts: Vec<Layout> = layouts.into_iter().collect();
+    for l in &layouts {
+        constraint::check(constraints, l)?;
+    }
+    Ok(layouts)
+}
+
+/// Orders results by score, best first, with NaN scores last.
+pub(crate) fn by_score(a: f32, b: f32) -> std::cmp::Ordering {
+    a.is_nan().cmp(&b.is_nan()).then(a.total_cmp(&b))
+}
+
+/// How a population-based optimizer creates the starting layouts of
+/// its members from the layout passed to `Optimizer::setup`. The first
+/// member always starts from that layout.
+///
+/// Only unpinned positions are moved, and only with swaps that keep
+/// the constraints satisfied.
+#[derive(Clone, Default)]
+pub enum Init {
+    /// Every member starts from the same layout.
+    #[default]
+    Clone,
+    /// The unpinned positions of each member are shuffled. With
+    /// constraints, swaps of the shuffle that would break them are
+    /// skipped.
+    Shuffle,
+    /// Each member gets this many random swaps.
+    Perturb(usize),
+    /// Members start from these layouts in turn, e.g. the results of
+    /// earlier runs. They must have the same characters as the layout
+    /// passed to `setup`, the same characters on its pinned positions,
+    /// and satisfy the constraints.
+    Layouts(Vec<Layout>),
+}
+
+impl Init {
+    /// The RNG stream of the start of member `index`, apart from the
+    /// streams optimizers use while running.
+    const STREAM: u64 = 1 << 63;
+
+    /// Creates the starting layout of member `index` from `l`.
+    pub(crate) fn member(
+        &self,
+        l: &Layout,
+        index: usize,
+        pins: &[Pos],
+        constraints: &[Constraint],
+        seed: u64,
+    ) -> Layout {
+        let free: Vec<Pos> = (0..l.0.len()).filter(|p| !pins.contains(p)).collect();
+        let mut rng = seeded_rng(seed, Self::STREAM | index as u64);
+        let mut member = l.clone();
+        let try_swap = |member: &mut Layout, swap: Swap| {
+            if constraint::allows_swap(constraints, member, &swap) {
+                member.swap(&swap);
+            }
+        };
+        match self {
+            _ if index == 0 => {}
+            Init::Clone => {}
+            Init::Shuffle => {
+                for i in (1..free.len()).rev() {
+                    let j = rng.gen_range(0..=i);
+                    try_swap(&mut member, Swap::new(free[i], free[j]));
+                }
+            }
+            Init::Perturb(k) => {
+                for _ in 0..*k {
+                    if let [a, b] = free.choose_multiple(&mut rng, 2).collect::<Vec<_>>()[..] {
+                        try_swap(&mut member, Swap::new(*a, *b));
+                    }
+                }
+            }
+            Init::Layouts(layouts) => {
+                if !layouts.is_empty() {
+                    member = layouts[(index - 1) % layouts.len()].clone();
+                }
+            }
+        }
+        member
+    }
+    /// Creates the starting layouts of `size` members from `l`.
+    pub(crate) fn population(
+        &self,
+        l: &Layout,
+        size: usize,
+        pins: &[Pos],
+        constraints: &[Constraint],
+        seed: u64,
+    ) -> Result<Vec<Layout>, SetupError> {
+        constraint::check(constraints, l)?;
+        if let Init::Layouts(layouts) = self {
+            let mut chars = l.0.clone();
+            chars.sort_unstable();
+            for (index, layout) in layouts.iter().enumerate() {
+                let mut other = layout.0.clone();
+                other.sort_unstable();
+                if other != chars {
+                    return Err(SetupError::Mismatch { index });
+                }
+                if let Some(pos) = pins.iter().find(|p| layout.0[**p] != l.0[**p]) {
+                    return Err(SetupError::Pinned { index, pos: *pos });
+                }
+                constraint::check(constraints, layout)?;
+            }
+        }
+        Ok((0..size)
+            .map(|index| self.member(l, index, pins, constraints, seed))
+            .collect())
+    }
+}
+
+pub trait Optimizer {
+    /// Prepares the optimizer for running, checking that the layout
+    /// satisfies the constraints.
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError>;
+    #[must_use]
+    fn pin(self, pins: Vec<usize>) -> Self;
+    /// Restricts the optimizer to layouts satisfying `constraints`.
+    #[must_use]
+    fn constrain(self, constraints: Vec<Constraint>) -> Self;
+    /// Reports progress and checks for cancellation during runs.
+    #[must_use]
+    fn control(self, control: RunControl) -> Self;
+    /// Makes runs reproducible: runs with the same seed, data and
+    /// objective give the same layouts. Without a seed, every run is
+    /// seeded randomly. Deterministic optimizers ignore it.
+    #[must_use]
+    fn seed(self, _seed: u64) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+    /// Runs the optimization for as long as needed. Layouts the
+    /// objective scores as NaN are sorted last.
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)>;
+    /// Like `Optimizer::run`, but fails if the objective scores any
+    /// of the resulting layouts as NaN.
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+/// Fails if the objective scores any of the starting `layouts` as NaN,
+/// so that `Optimizer::try_run` can fail before a full run.
+pub(crate) fn check_scores(
+    analyzer: &Analyzer,
+    objective: &(dyn Objective + Send + Sync),
+    layouts: &[Layout],
+) -> Result<(), Error> {
+    if layouts
+        .iter()
+        .any(|l| objective.score(&analyzer.calc_stats(l)).is_nan())
+    {
+        Err(Error::NanScore)
+    } else {
+        Ok(())
+    }
+}
+
+/// Fails if the objective scored any of the results of a run as NaN.
+pub(crate) fn check_results(results: Vec<(Layout, f32)>) -> Result<Vec<(Layout, f32)>, Error> {
+    if results.iter().any(|(_, score)| score.is_nan()) {
+        Err(Error::NanScore)
+    } else {
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::{MetricAmount, MetricData, NstrokeData},
+        Corpus, NgramType, Nstroke,
+    };
+    use std::sync::atomic::AtomicU64;
+    pub(super) fn setup_analyzer() -> Analyzer {
+        let mut corpus = Corpus::with_char_list(
+            "abcdefghijklmnopqrstuvwxyz,./;"
+                .chars()
+                .map(|c| vec![c])
+                .collect(),
+        );
+
+        let text = "the quick brown fox jumps over the lazy dog";
+        corpus.add_str(text);
+
+        let metrics = vec![NgramType::Bigram];
+        let mut strokes: Vec<NstrokeData> = vec![];
+        // bigram alternation
+        for a in 0..30 {
+            for b in 0..30 {
+                if (a < 15) == (b < 15) {
+                    strokes.push(NstrokeData::new(
+                        Nstroke::Bistroke([a, b]),
+                        vec![MetricAmount::new(0, 1.0)],
+                    ));
+                }
+            }
+        }
+        let data = MetricData::from(metrics, strokes, 30);
+        Analyzer::from(data, corpus)
+    }
+    #[test]
+    fn test_run_control() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        let start = objective.score(&analyzer.calc_stats(&qwerty));
+
+        let reports = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&reports);
+        let control = RunControl::new().observe(100, move |progress| {
+            assert_eq!(0, progress.iteration % 100);
+            assert!(progress.member < 2);
+            assert!(progress.best_score <= start);
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let mut optimizer = AnnealingOptimizer::new(2, 1000).control(control).seed(1);
+        optimizer.setup(qwerty.clone()).expect("no constraints");
+        let _ = optimizer.run(&analyzer, &objective);
+        assert_eq!(20, reports.load(Ordering::Relaxed));
+
+        // cancelling from the observer stops the run after one report
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let control = RunControl::new()
+            .cancel_with(token)
+            .observe(1, move |_| canceller.cancel());
+        let mut optimizer = TabuOptimizer::new(100).control(control);
+        optimizer.setup(qwerty.clone()).expect("no constraints");
+        let optimized = optimizer.run(&analyzer, &objective);
+        assert_eq!(1, optimizer.history()[0].len());
+        assert!(optimized[0].1 < start);
+
+        let control = RunControl::new();
+        control.token().cancel();
+        let mut optimizer = AnnealingOptimizer::new(2, 1000).control(control).seed(1);
+        optimizer.setup(qwerty.clone()).expect("no constraints");
+        for (layout, _) in optimizer.run(&analyzer, &objective) {
+            assert_eq!(qwerty.0, layout.0);
+        }
+
+        // every optimizer stops at the iteration limit, with exhaustive
+        // search reaching every arrangement by not bounding
+        let unbounded = AnonymousObjective {
+            function: |stats| stats[0],
+        };
+        let free = [6, 12, 16, 25, 1];
+        let pins: Vec<Pos> = (0..30).filter(|p| !free.contains(p)).collect();
+        let criteria = vec![Criterion {
+            metric: 0,
+            sense: Sense::Minimize,
+        }];
+        assert_eq!(
+            3,
+            limited_run(
+                GreedyOptimizer::new(Strategy::FirstImprovement),
+                &analyzer,
+                &objective,
+                &qwerty
+            ),
+            "greedy"
+        );
+        assert_eq!(
+            3,
+            limited_run(
+                AnnealingOptimizer::new(2, 1000).seed(1),
+                &analyzer,
+                &objective,
+                &qwerty
+            ),
+            "annealing"
+        );
+        assert_eq!(
+            3,
+            limited_run(TabuOptimizer::new(100), &analyzer, &objective, &qwerty),
+            "tabu"
+        );
+        assert_eq!(
+            3,
+            limited_run(
+                GeneticOptimizer::new(4, 100).seed(1),
+                &analyzer,
+                &objective,
+                &qwerty
+            ),
+            "genetic"
+        );
+        assert_eq!(
+            3,
+            limited_run(
+                NsgaOptimizer::new(criteria, 4, 100).seed(1),
+                &analyzer,
+                &objective,
+                &qwerty
+            ),
+            "nsga"
+        );
+        assert_eq!(
+            3,
+            limited_run(
+                ExhaustiveOptimizer::new().pin(pins),
+                &analyzer,
+                &unbounded,
+                &qwerty
+            ),
+            "exhaustive"
+        );
+    }
+    /// Runs `optimizer` limited to 3 iterations, returning the last
+    /// iteration it reported.
+    fn limited_run(
+        optimizer: impl Optimizer,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        l: &Layout,
+    ) -> u64 {
+        let last = Arc::new(AtomicU64::new(0));
+        let observed = Arc::clone(&last);
+        let control = RunControl::new()
+            .max_iterations(3)
+            .observe(1, move |progress| {
+                observed.fetch_max(progress.iteration, Ordering::Relaxed);
+            });
+        let mut optimizer = optimizer.control(control);
+        optimizer.setup(l.clone()).expect("no constraints");
+        let _ = optimizer.run(analyzer, objective);
+        last.load(Ordering::Relaxed)
+    }
+    #[test]
+    fn test_init() {
+        let analyzer = setup_analyzer();
+        let corpus = &analyzer.corpus;
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        // ',' on the left hand, apart from '.'
+        let mut other = qwerty.clone();
+        other.swap(&Swap::new(23, 0));
+        let constraints = vec![Constraint::SameGroup {
+            chars: vec![corpus.corpus_char(','), corpus.corpus_char('.')],
+            groups: vec![(0..15).collect(), (15..30).collect()],
+        }];
+        let pins = vec![0, 1, 2];
+
+        for init in [Init::Shuffle, Init::Perturb(3)] {
+            let population = init
+                .population(&qwerty, 4, &pins, &constraints, 1)
+                .expect("qwerty satisfies the constraints");
+            assert_eq!(qwerty.0, population[0].0);
+            for member in &population[1..] {
+                assert_ne!(qwerty.0, member.0);
+                assert_eq!(qwerty.0[..3], member.0[..3]);
+                assert!(constraint::check(&constraints, member).is_ok());
+            }
+            if let Init::Perturb(k) = init {
+                assert!(population[1].changed_positions(&qwerty).len() <= 2 * k);
+            }
+        }
+
+        let init = Init::Layouts(vec![other.clone()]);
+        let population = init
+            .population(&qwerty, 3, &[], &[], 1)
+            .expect("no constraints");
+        assert_eq!(qwerty.0, population[0].0);
+        assert_eq!(other.0, population[1].0);
+        assert_eq!(other.0, population[2].0);
+        assert!(matches!(
+            init.population(&qwerty, 3, &[], &constraints, 1),
+            Err(SetupError::Constraint(_))
+        ));
+        assert!(matches!(
+            init.population(&qwerty, 3, &[23], &[], 1),
+            Err(SetupError::Pinned { index: 0, pos: 23 })
+        ));
+        let short = Layout(qwerty.0[1..].to_vec());
+        let init = Init::Layouts(vec![other, short]);
+        assert!(matches!(
+            init.population(&qwerty, 3, &[], &[], 1),
+            Err(SetupError::Mismatch { index: 1 })
+        ));
+    }
+    #[test]
+    fn test_seed() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+        let same = |first: Vec<(Layout, f32)>, second: Vec<(Layout, f32)>| {
+            assert!(!first.is_empty());
+            for ((a, score_a), (b, score_b)) in first.iter().zip(&second) {
+                assert_eq!(a.0, b.0, "runs with the same seed should match");
+                assert_eq!(score_a, score_b);
+            }
+        };
+        let annealing = || {
+            let mut optimizer = AnnealingOptimizer::new(4, 500)
+                .with_init(Init::Shuffle)
+                .with_restarts(1)
+                .seed(42);
+            optimizer.setup(qwerty.clone()).expect("no constraints");
+            optimizer.run(&analyzer, &objective)
+        };
+        same(annealing(), annealing());
+        let genetic = || {
+            let mut optimizer = GeneticOptimizer::new(10, 10).seed(42);
+            optimizer.setup(qwerty.clone()).expect("no constraints");
+            optimizer.run(&analyzer, &objective)
+        };
+        same(genetic(), genetic());
+        let nsga = || {
+            let criteria = vec![Criterion::minimize(0)];
+            let mut optimizer = NsgaOptimizer::new(criteria, 10, 10).seed(42);
+            optimizer.setup(qwerty.clone()).expect("no constraints");
+            optimizer.run(&analyzer, &objective)
+        };
+        same(nsga(), nsga());
+    }
+}
@@ -0,0 +1,361 @@
+use super::genetic::Variation;
+use super::rank::{crowding_distances, pareto_fronts, Criterion};
+use super::{
+    by_score, check_results, check_scores, seeded_rng, Crossover, Init, Objective, Optimizer,
+    Progress, RunControl, SetupError,
+};
+use crate::constraint::{self, Constraint};
+use crate::{analysis::Analyzer, CorpusChar, Error, Layout, Pos};
+use rand::prelude::*;
+use rayon::prelude::*;
+use std::collections::HashSet;
+
+/// A multi-objective `Optimizer` using NSGA-II selection over
+/// `criteria`. Instead of collapsing the stats into a single score, it
+/// ranks layouts by Pareto dominance, and prefers layouts in sparse
+/// parts of each front to keep the population spread out.
+///
+/// Every non-dominated layout found is kept in an archive, so
+/// trade-offs can be picked after the run with
+/// `NsgaOptimizer::archive`. `Optimizer::run` returns the archive
+/// sorted by the objective, which is only used for that ordering.
+pub struct NsgaOptimizer {
+    layouts: Vec<Layout>,
+    pins: Vec<usize>,
+    constraints: Vec<Constraint>,
+    control: RunControl,
+    seed: Option<u64>,
+    archive: Vec<(Layout, Vec<f32>)>,
+    pub criteria: Vec<Criterion>,
+    pub population_size: usize,
+    pub generations: u64,
+    pub crossover: Crossover,
+    /// The probability of a child being mutated with a random swap.
+    pub mutation_rate: f64,
+    /// The most layouts kept in the archive. When it's exceeded, the
+    /// most crowded layouts are dropped.
+    pub archive_size: usize,
+    /// How the starting population is created.
+    pub init: Init,
+}
+
+/// A member of the population with its Pareto rank and crowding
+/// distance.
+struct Member {
+    layout: Layout,
+    stats: Vec<f32>,
+    rank: usize,
+    crowding: f32,
+}
+
+impl Member {
+    /// Whether the member should be preferred over `other`: it's in a
+    /// better front, or in a less crowded part of the same front.
+    fn beats(&self, other: &Member) -> bool {
+        self.rank < other.rank || (self.rank == other.rank && self.crowding > other.crowding)
+    }
+}
+
+impl NsgaOptimizer {
+    #[must_use]
+    pub fn new(criteria: Vec<Criterion>, population_size: usize, generations: u64) -> Self {
+        Self {
+            layouts: Vec::with_capacity(population_size),
+            pins: vec![],
+            constraints: vec![],
+            control: RunControl::default(),
+            seed: None,
+            archive: vec![],
+            criteria,
+            population_size,
+            generations,
+            crossover: Crossover::default(),
+            mutation_rate: 0.2,
+            archive_size: 100,
+            init: Init::Shuffle,
+        }
+    }
+    #[must_use]
+    pub fn with_crossover(mut self, crossover: Crossover) -> Self {
+        self.crossover = crossover;
+        self
+    }
+    #[must_use]
+    pub fn with_init(mut self, init: Init) -> Self {
+        self.init = init;
+        self
+    }
+    /// The non-dominated layouts found by the last run, with their
+    /// stats.
+    #[must_use]
+    pub fn archive(&self) -> &[(Layout, Vec<f32>)] {
+        &self.archive
+    }
+    /// Keeps the best `size` of `candidates` by rank and crowding
+    /// distance.
+    fn survivors(&self, candidates: Vec<(Layout, Vec<f32>)>, size: usize) -> Vec<Member> {
+        let stats: Vec<Vec<f32>> = candidates.iter().map(|(_, s)| s.clone()).collect();
+        let mut candidates: Vec<Option<(Layout, Vec<f32>)>> =
+            candidates.into_iter().map(Some).collect();
+        let mut members = Vec::with_capacity(size);
+        for (rank, front) in pareto_fronts(&stats, &self.criteria)
+            .into_iter()
+            .enumerate()
+        {
+            if members.len() >= size {
+                break;
+            }
+            let crowding = crowding_distances(&stats, &front, &self.criteria);
+            let mut front: Vec<(usize, f32)> = front.into_iter().zip(crowding).collect();
+            front.sort_by(|a, b| b.1.total_cmp(&a.1));
+            front.truncate(size - members.len());
+            for (i, crowding) in front {
+                let (layout, stats) = candidates[i].take().expect("fronts are disjoint");
+                members.push(Member {
+                    layout,
+                    stats,
+                    rank,
+                    crowding,
+                });
+            }
+        }
+        members
+    }
+    /// Adds the non-dominated `candidates` to the archive, removing
+    /// the layouts they dominate.
+    fn update_archive(&mut self, candidates: impl IntoIterator<Item = (Layout, Vec<f32>)>) {
+        let mut seen: HashSet<Vec<CorpusChar>> =
+            self.archive.iter().map(|(l, _)| l.0.clone()).collect();
+        let mut pool = std::mem::take(&mut self.archive);
+        pool.extend(
+            candidates
+                .into_iter()
+                .filter(|(l, _)| seen.insert(l.0.clone())),
+        );
+        let stats: Vec<Vec<f32>> = pool.iter().map(|(_, s)| s.clone()).collect();
+        let front = pareto_fronts(&stats, &self.criteria)
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let mut front: Vec<(usize, f32)> = if front.len() > self.archive_size {
+            let crowding = crowding_distances(&stats, &front, &self.criteria);
+            let mut front: Vec<(usize, f32)> = front.into_iter().zip(crowding).collect();
+            front.sort_by(|a, b| b.1.total_cmp(&a.1));
+            front.truncate(self.archive_size);
+            front
+        } else {
+            front.into_iter().map(|i| (i, 0.0)).collect()
+        };
+        front.sort_unstable_by_key(|(i, _)| *i);
+        let mut pool: Vec<Option<(Layout, Vec<f32>)>> = pool.into_iter().map(Some).collect();
+        self.archive = front
+            .into_iter()
+            .filter_map(|(i, _)| pool[i].take())
+            .collect();
+    }
+}
+
+impl Optimizer for NsgaOptimizer {
+    /// Creates the starting population from `l` with `init`.
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError> {
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        self.layouts = self.init.population(
+            &l,
+            self.population_size,
+            &self.pins,
+            &self.constraints,
+            seed,
+        )?;
+        Ok(())
+    }
+
+    fn pin(mut self, pins: Vec<usize>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    fn constrain(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    fn control(mut self, control: RunControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)> {
+        let evaluated: Vec<(Layout, Vec<f32>)> = std::mem::take(&mut self.layouts)
+            .into_par_iter()
+            .map(|l| {
+                let stats = analyzer.calc_stats(&l);
+                (l, stats)
+            })
+            .collect();
+        let Some(len) = evaluated.first().map(|(l, _)| l.0.len()) else {
+            return vec![];
+        };
+        self.archive.clear();
+        self.update_archive(evaluated.iter().cloned());
+        let size = evaluated.len();
+        let mut population = self.survivors(evaluated, size);
+
+        let free: Vec<Pos> = (0..len).filter(|p| !self.pins.contains(p)).collect();
+        let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        for generation in 1..=self.generations {
+            if self.control.should_stop(generation - 1) {
+                break;
+            }
+            let variation = Variation {
+                crossover: self.crossover,
+                mutation_rate: self.mutation_rate,
+                constraints: &self.constraints,
+                free: &free,
+            };
+            // binary tournaments on rank and crowding distance
+            let select = |rng: &mut StdRng| {
+                let a = &population[rng.gen_range(0..population.len())];
+                let b = &population[rng.gen_range(0..population.len())];
+                if b.beats(a) {
+                    b
+                } else {
+                    a
+                }
+            };
+            let children: Vec<(Layout, Vec<f32>)> = (0..size)
+                .into_par_iter()
+                .map(|child| {
+                    let mut rng = seeded_rng(seed, generation * size as u64 + child as u64);
+                    let a = select(&mut rng);
+                    let b = select(&mut rng);
+                    let child = variation.breed(&a.layout, &b.layout, &mut rng);
+                    if constraint::check(&self.constraints, &child).is_ok() {
+                        let stats = analyzer.calc_stats(&child);
+                        (child, stats)
+                    } else {
+                        let parent = if b.beats(a) { b } else { a };
+                        (parent.layout.clone(), parent.stats.clone())
+                    }
+                })
+                .collect();
+            self.update_archive(children.iter().cloned());
+            let candidates = population
+                .into_iter()
+                .map(|m| (m.layout, m.stats))
+                .chain(children)
+                .collect();
+            population = self.survivors(candidates, size);
+
+            if self.control.should_report(generation) {
+                for (member, (layout, stats)) in self.archive.iter().enumerate() {
+                    self.control.report(&Progress {
+                        member,
+                        iteration: generation,
+                        best_score: objective.score(stats),
+                        best_layout: layout,
+                    });
+                }
+            }
+        }
+        self.layouts = population.into_iter().map(|m| m.layout).collect();
+
+        let mut results: Vec<(Layout, f32)> = self
+            .archive
+            .iter()
+            .map(|(l, stats)| (l.clone(), objective.score(stats)))
+            .collect();
+        results.sort_by(|a, b| by_score(a.1, b.1));
+        results
+    }
+
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_scores(analyzer, objective, &self.layouts)?;
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{MetricAmount, MetricData, NstrokeData};
+    use crate::opt::{dominates, Weight, WeightsObjective};
+    use crate::{Corpus, NgramType, Nstroke};
+    #[test]
+    fn test_nsga() {
+        let mut corpus = Corpus::with_char_list(
+            "abcdefghijklmnopqrstuvwxyz,./;"
+                .chars()
+                .map(|c| vec![c])
+                .collect(),
+        );
+        for text in [
+            "the quick brown fox jumps over the lazy dog",
+            "pack my box with five dozen liquor jugs",
+        ] {
+            corpus.add_str(text);
+        }
+        // same hand bigrams, and left hand bigrams, which conflict
+        // when the second is maximized
+        let metrics = vec![NgramType::Bigram, NgramType::Bigram];
+        let mut strokes = vec![];
+        for a in 0..30 {
+            for b in 0..30 {
+                let mut amounts = vec![];
+                if (a < 15) == (b < 15) {
+                    amounts.push(MetricAmount::new(0, 1.0));
+                }
+                if a < 15 && b < 15 {
+                    amounts.push(MetricAmount::new(1, 1.0));
+                }
+                if !amounts.is_empty() {
+                    strokes.push(NstrokeData::new(Nstroke::Bistroke([a, b]), amounts));
+                }
+            }
+        }
+        let analyzer = Analyzer::from(MetricData::from(metrics, strokes, 30), corpus);
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let criteria = vec![Criterion::minimize(0), Criterion::maximize(1)];
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+
+        let mut optimizer = NsgaOptimizer::new(criteria.clone(), 20, 30)
+            .pin(vec![0])
+            .seed(11);
+        optimizer.setup(qwerty.clone()).expect("no constraints");
+        let results = optimizer.run(&analyzer, &objective);
+        let archive = optimizer.archive();
+        assert_eq!(archive.len(), results.len());
+        assert!(results.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(archive.len() > 1, "the criteria should conflict");
+
+        let start = analyzer.calc_stats(&qwerty);
+        for (layout, stats) in archive {
+            assert_eq!(qwerty.0[0], layout.0[0]);
+            assert_eq!(&analyzer.calc_stats(layout), stats);
+            assert!(!dominates(&criteria, &start, stats));
+            assert!(archive
+                .iter()
+                .all(|(_, other)| !dominates(&criteria, other, stats)));
+        }
+        assert!(archive
+            .iter()
+            .any(|(_, stats)| dominates(&criteria, stats, &start)));
+    }
+}
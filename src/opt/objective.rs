@@ -0,0 +1,403 @@
+use crate::analysis::MetricIndex;
+use std::cell::RefCell;
+
+thread_local! {
+    /// The stats after a change, reused by `Objective::score_transition`
+    /// so that optimizers don't allocate for every swap they evaluate.
+    static AFTER: RefCell<Vec<f32>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Trait for objective functions, used in optimization.
+pub trait Objective {
+    /// Returns how well the stats meet the objective. Lower values
+    /// should mean a better fit to the objective.
+    #[must_use]
+    fn score(&self, stats: &[f32]) -> f32;
+    /// Returns how much the score changes when `diffs` are added to
+    /// `stats`, which optimizers use to decide whether to make a swap.
+    /// Negative values mean the change is an improvement.
+    ///
+    /// By default, this scores the stats before and after the change,
+    /// building the stats after it in a buffer kept per thread. Linear
+    /// objectives can score the diffs on their own instead.
+    #[must_use]
+    fn score_transition(&self, stats: &[f32], diffs: &[f32]) -> f32 {
+        let after = stats.iter().zip(diffs).map(|(s, d)| s + d);
+        let score = AFTER.with(|buffer| {
+            // `score` may itself score a transition, which needs its
+            // own buffer
+            let mut buffer = buffer.try_borrow_mut().ok()?;
+            buffer.clear();
+            buffer.extend(after.clone());
+            Some(self.score(&buffer))
+        });
+        let score = score.unwrap_or_else(|| self.score(&after.collect::<Vec<f32>>()));
+        score - self.score(stats)
+    }
+    /// The weight of each stat, if the score is the sum of the stats
+    /// multiplied by their weights. Stats past the end of the weights
+    /// have a weight of 0. Returns `None` for objectives that aren't
+    /// linear, which is the default.
+    #[must_use]
+    fn linear_weights(&self) -> Option<Vec<f32>> {
+        None
+    }
+}
+
+/// Adds `b` to `a` element-wise, extending `a` if `b` is longer.
+fn add_weights(a: &mut Vec<f32>, b: &[f32]) {
+    if a.len() < b.len() {
+        a.resize(b.len(), 0.0);
+    }
+    for (a, b) in a.iter_mut().zip(b) {
+        *a += b;
+    }
+}
+
+pub struct Weight {
+    pub metric: MetricIndex,
+    pub weight: f32,
+}
+
+impl Weight {
+    #[must_use]
+    pub fn new(metric: MetricIndex, weight: f32) -> Self {
+        Self { metric, weight }
+    }
+}
+
+/// The most basic kind of objective function. Each metric is
+/// associated with a multiplicative weight to create a single
+/// composite value.
+pub struct WeightsObjective {
+    pub weights: Vec<Weight>,
+}
+
+impl WeightsObjective {
+    #[must_use]
+    pub fn new(weights: Vec<Weight>) -> Self {
+        WeightsObjective { weights }
+    }
+}
+
+impl Objective for WeightsObjective {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.weights
+            .iter()
+            .fold(0.0, |acc, Weight { metric, weight }| {
+                acc + (weight * stats[*metric])
+            })
+    }
+    fn score_transition(&self, _stats: &[f32], diffs: &[f32]) -> f32 {
+        self.score(diffs)
+    }
+    fn linear_weights(&self) -> Option<Vec<f32>> {
+        let mut weights = vec![];
+        for Weight { metric, weight } in &self.weights {
+            if weights.len() <= *metric {
+                weights.resize(metric + 1, 0.0);
+            }
+            weights[*metric] += weight;
+        }
+        Some(weights)
+    }
+}
+
+pub struct AnonymousObjective {
+    pub function: fn(&[f32]) -> f32,
+}
+
+impl Objective for AnonymousObjective {
+    fn score(&self, stats: &[f32]) -> f32 {
+        (self.function)(stats)
+    }
+}
+
+/// A scoring closure that can be shared between threads.
+pub type ObjectiveFn = Box<dyn Fn(&[f32]) -> f32 + Send + Sync>;
+
+/// An objective from a closure, which unlike `AnonymousObjective` can
+/// capture configuration such as target values.
+pub struct ClosureObjective {
+    pub function: ObjectiveFn,
+}
+
+impl ClosureObjective {
+    #[must_use]
+    pub fn new(function: impl Fn(&[f32]) -> f32 + Send + Sync + 'static) -> Self {
+        Self {
+            function: Box::new(function),
+        }
+    }
+}
+
+impl Objective for ClosureObjective {
+    fn score(&self, stats: &[f32]) -> f32 {
+        (self.function)(stats)
+    }
+}
+
+/// An objective that can be shared between threads, used to compose
+/// objectives.
+pub type BoxedObjective = Box<dyn Objective + Send + Sync>;
+
+impl Objective for BoxedObjective {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.as_ref().score(stats)
+    }
+    fn score_transition(&self, stats: &[f32], diffs: &[f32]) -> f32 {
+        self.as_ref().score_transition(stats, diffs)
+    }
+    fn linear_weights(&self) -> Option<Vec<f32>> {
+        self.as_ref().linear_weights()
+    }
+}
+
+/// The value of a single stat.
+pub struct Metric(pub MetricIndex);
+
+impl Objective for Metric {
+    fn score(&self, stats: &[f32]) -> f32 {
+        stats[self.0]
+    }
+    fn score_transition(&self, _stats: &[f32], diffs: &[f32]) -> f32 {
+        diffs[self.0]
+    }
+    fn linear_weights(&self) -> Option<Vec<f32>> {
+        let mut weights = vec![0.0; self.0 + 1];
+        weights[self.0] = 1.0;
+        Some(weights)
+    }
+}
+
+/// Penalizes `inner` going above `threshold`, by `slope` per unit it
+/// exceeds it. Below the threshold, the score is 0. For example,
+/// `Hinge::new(Metric(sfb), 0.01, 1000.0)` heavily penalizes SFBs
+/// above 1%.
+pub struct Hinge {
+    pub inner: BoxedObjective,
+    pub threshold: f32,
+    pub slope: f32,
+}
+
+impl Hinge {
+    #[must_use]
+    pub fn new(inner: impl Objective + Send + Sync + 'static, threshold: f32, slope: f32) -> Self {
+        Self {
+            inner: Box::new(inner),
+            threshold,
+            slope,
+        }
+    }
+}
+
+impl Objective for Hinge {
+    fn score(&self, stats: &[f32]) -> f32 {
+        (self.inner.score(stats) - self.threshold).max(0.0) * self.slope
+    }
+}
+
+/// The ratio of two objectives, e.g. rolls to redirects. Scores 0
+/// when the denominator is 0.
+pub struct Ratio {
+    pub numerator: BoxedObjective,
+    pub denominator: BoxedObjective,
+}
+
+impl Ratio {
+    #[must_use]
+    pub fn new(
+        numerator: impl Objective + Send + Sync + 'static,
+        denominator: impl Objective + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            numerator: Box::new(numerator),
+            denominator: Box::new(denominator),
+        }
+    }
+}
+
+impl Objective for Ratio {
+    fn score(&self, stats: &[f32]) -> f32 {
+        let denominator = self.denominator.score(stats);
+        if denominator == 0.0 {
+            0.0
+        } else {
+            self.numerator.score(stats) / denominator
+        }
+    }
+}
+
+/// How far `inner` is from `target`, in either direction.
+pub struct Target {
+    pub inner: BoxedObjective,
+    pub target: f32,
+}
+
+impl Target {
+    #[must_use]
+    pub fn new(inner: impl Objective + Send + Sync + 'static, target: f32) -> Self {
+        Self {
+            inner: Box::new(inner),
+            target,
+        }
+    }
+}
+
+impl Objective for Target {
+    fn score(&self, stats: &[f32]) -> f32 {
+        (self.inner.score(stats) - self.target).abs()
+    }
+}
+
+/// `inner` multiplied by a constant factor.
+pub struct Scaled {
+    pub inner: BoxedObjective,
+    pub factor: f32,
+}
+
+impl Scaled {
+    #[must_use]
+    pub fn new(inner: impl Objective + Send + Sync + 'static, factor: f32) -> Self {
+        Self {
+            inner: Box::new(inner),
+            factor,
+        }
+    }
+}
+
+impl Objective for Scaled {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.inner.score(stats) * self.factor
+    }
+    fn score_transition(&self, stats: &[f32], diffs: &[f32]) -> f32 {
+        self.inner.score_transition(stats, diffs) * self.factor
+    }
+    fn linear_weights(&self) -> Option<Vec<f32>> {
+        let mut weights = self.inner.linear_weights()?;
+        for w in &mut weights {
+            *w *= self.factor;
+        }
+        Some(weights)
+    }
+}
+
+/// The sum of several objectives.
+pub struct Sum(pub Vec<BoxedObjective>);
+
+impl Objective for Sum {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.0.iter().map(|o| o.score(stats)).sum()
+    }
+    fn score_transition(&self, stats: &[f32], diffs: &[f32]) -> f32 {
+        self.0
+            .iter()
+            .map(|o| o.score_transition(stats, diffs))
+            .sum()
+    }
+    fn linear_weights(&self) -> Option<Vec<f32>> {
+        let mut weights = vec![];
+        for o in &self.0 {
+            add_weights(&mut weights, &o.linear_weights()?);
+        }
+        Some(weights)
+    }
+}
+
+/// The product of several objectives.
+pub struct Product(pub Vec<BoxedObjective>);
+
+impl Objective for Product {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.0.iter().map(|o| o.score(stats)).product()
+    }
+}
+
+/// The lowest score of several objectives, or infinity if there are
+/// none.
+pub struct Min(pub Vec<BoxedObjective>);
+
+impl Objective for Min {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.0
+            .iter()
+            .map(|o| o.score(stats))
+            .fold(f32::INFINITY, f32::min)
+    }
+}
+
+/// The highest score of several objectives, e.g. to optimize the
+/// worst of several metrics. Negative infinity if there are none.
+pub struct Max(pub Vec<BoxedObjective>);
+
+impl Objective for Max {
+    fn score(&self, stats: &[f32]) -> f32 {
+        self.0
+            .iter()
+            .map(|o| o.score(stats))
+            .fold(f32::NEG_INFINITY, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_combinators() {
+        // sfb, lsb and rolls
+        let stats = [0.02, 3.0, 0.5];
+        assert_eq!(0.0, Hinge::new(Metric(0), 0.03, 100.0).score(&stats));
+        assert!((Hinge::new(Metric(0), 0.01, 100.0).score(&stats) - 1.0).abs() < 1e-5);
+        assert_eq!(6.0, Ratio::new(Metric(1), Metric(2)).score(&stats));
+        assert_eq!(0.0, Ratio::new(Metric(0), Metric(1)).score(&[1.0, 0.0]));
+        assert_eq!(1.0, Target::new(Metric(1), 4.0).score(&stats));
+        assert_eq!(1.0, Target::new(Metric(1), 2.0).score(&stats));
+        let target = 0.01;
+        let closure = ClosureObjective::new(move |stats| (stats[0] - target).abs());
+        assert_eq!(0.01, closure.score(&stats));
+
+        let all = || -> Vec<BoxedObjective> {
+            vec![
+                Box::new(Metric(0)),
+                Box::new(Metric(1)),
+                Box::new(Metric(2)),
+            ]
+        };
+        assert_eq!(3.52, Sum(all()).score(&stats));
+        assert_eq!(0.03, Product(all()).score(&stats));
+        assert_eq!(0.02, Min(all()).score(&stats));
+        assert_eq!(3.0, Max(all()).score(&stats));
+
+        // 10 * sfb + the lsb above 2, weighted against rolls
+        let objective = Sum(vec![
+            Box::new(Scaled::new(Metric(0), 10.0)),
+            Box::new(Hinge::new(Metric(1), 2.0, 1.0)),
+            Box::new(WeightsObjective::new(vec![Weight::new(2, -1.0)])),
+        ]);
+        assert!((objective.score(&stats) - 0.7).abs() < 1e-5);
+        assert!(objective.linear_weights().is_none());
+        let linear = Sum(vec![
+            Box::new(Scaled::new(Metric(0), 10.0)),
+            Box::new(WeightsObjective::new(vec![Weight::new(2, -1.0)])),
+        ]);
+        assert_eq!(Some(vec![10.0, 0.0, -1.0]), linear.linear_weights());
+        // the lsb goes from 3 to 1, which only lowers the hinge by 1
+        let diffs = [0.01, -2.0, 0.0];
+        assert!((objective.score_transition(&stats, &diffs) + 0.9).abs() < 1e-5);
+        assert_eq!(
+            -2.0,
+            Target::new(Metric(1), 1.0).score_transition(&stats, &diffs)
+        );
+        assert_eq!(
+            2.0,
+            Target::new(Metric(1), 3.0).score_transition(&stats, &diffs)
+        );
+        assert_eq!(-2.0, Metric(1).score_transition(&stats, &diffs));
+        // scoring a transition while scoring one
+        let nested = ClosureObjective::new(move |after| {
+            let inner = ClosureObjective::new(|stats| stats[1] * stats[1]);
+            after[0] + inner.score_transition(&[0.0, 1.0], &[0.0, 1.0])
+        });
+        assert!((nested.score_transition(&stats, &diffs) - 0.01).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::analysis::{Analyzer, MetricIndex};
+use crate::{CorpusChar, Layout, Permutation};
+use std::collections::HashSet;
+
+/// Removes duplicate layouts from `results`, keeping the first of
+/// each, which is the best scored one for the sorted output of
+/// `Optimizer::run`.
+///
+/// If `mirror` is given, a layout and its mirror image, e.g. with the
+/// hands swapped, are also considered duplicates.
+#[must_use]
+pub fn dedup(results: Vec<(Layout, f32)>, mirror: Option<&Permutation>) -> Vec<(Layout, f32)> {
+    let mut seen: HashSet<Vec<CorpusChar>> = HashSet::new();
+    results
+        .into_iter()
+        .filter(|(l, _)| {
+            let key = match mirror {
+                Some(mirror) => {
+                    let mut mirrored = l.clone();
+                    mirrored.permute(mirror);
+                    mirrored.0.min(l.0.clone())
+                }
+                None => l.0.clone(),
+            };
+            seen.insert(key)
+        })
+        .collect()
+}
+
+/// Whether a stat should be minimized or maximized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sense {
+    Minimize,
+    Maximize,
+}
+
+/// A stat layouts are ranked by in a Pareto ranking.
+#[derive(Debug, Clone, Copy)]
+pub struct Criterion {
+    pub metric: MetricIndex,
+    pub sense: Sense,
+}
+
+impl Criterion {
+    #[must_use]
+    pub fn minimize(metric: MetricIndex) -> Self {
+        Self {
+            metric,
+            sense: Sense::Minimize,
+        }
+    }
+    #[must_use]
+    pub fn maximize(metric: MetricIndex) -> Self {
+        Self {
+            metric,
+            sense: Sense::Maximize,
+        }
+    }
+    /// Whether stats `a` are strictly better than `b` on this
+    /// criterion.
+    fn better(&self, a: &[f32], b: &[f32]) -> bool {
+        match self.sense {
+            Sense::Minimize => a[self.metric] < b[self.metric],
+            Sense::Maximize => a[self.metric] > b[self.metric],
+        }
+    }
+}
+
+/// Whether stats `a` dominate `b`: they're at least as good on every
+/// criterion, and better on at least one.
+#[must_use]
+pub fn dominates(criteria: &[Criterion], a: &[f32], b: &[f32]) -> bool {
+    criteria.iter().all(|c| !c.better(b, a)) && criteria.iter().any(|c| c.better(a, b))
+}
+
+/// Sorts stats into Pareto fronts, returning the indices in each. The
+/// first front holds the stats no others dominate, the second the
+/// ones only dominated by the first front, and so on.
+#[must_use]
+pub fn pareto_fronts(stats: &[Vec<f32>], criteria: &[Criterion]) -> Vec<Vec<usize>> {
+    let n = stats.len();
+    // the indices each one dominates, and how many dominate it
+    let mut dominated: Vec<Vec<usize>> = vec![vec![]; n];
+    let mut dominators = vec![0; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            if dominates(criteria, &stats[i], &stats[j]) {
+                dominated[i].push(j);
+                dominators[j] += 1;
+            } else if dominates(criteria, &stats[j], &stats[i]) {
+                dominated[j].push(i);
+                dominators[i] += 1;
+            }
+        }
+    }
+    let mut fronts = vec![];
+    let mut front: Vec<usize> = (0..n).filter(|i| dominators[*i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = vec![];
+        for i in &front {
+            for j in &dominated[*i] {
+                dominators[*j] -= 1;
+                if dominators[*j] == 0 {
+                    next.push(*j);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+/// The crowding distance of each index of `front`: how far its stats
+/// are from their neighbours in the front, summed over the criteria
+/// and normalized by each criterion's range. The extremes of each
+/// criterion get an infinite distance, so preferring larger distances
+/// keeps the front spread out.
+#[must_use]
+pub fn crowding_distances(stats: &[Vec<f32>], front: &[usize], criteria: &[Criterion]) -> Vec<f32> {
+    let mut distances = vec![0.0; front.len()];
+    let mut order: Vec<usize> = (0..front.len()).collect();
+    for c in criteria {
+        let value = |i: usize| stats[front[i]][c.metric];
+        order.sort_by(|a, b| value(*a).total_cmp(&value(*b)));
+        let (Some(first), Some(last)) = (order.first(), order.last()) else {
+            return distances;
+        };
+        let range = value(*last) - value(*first);
+        distances[*first] = f32::INFINITY;
+        distances[*last] = f32::INFINITY;
+        if range > 0.0 {
+            for w in order.windows(3) {
+                distances[w[1]] += (value(w[2]) - value(w[0])) / range;
+            }
+        }
+    }
+    distances
+}
+
+/// Returns the layouts of `results` that no other layout dominates on
+/// `criteria`, along with their stats, instead of ranking them by a
+/// single score.
+#[must_use]
+pub fn pareto_front(
+    analyzer: &Analyzer,
+    results: Vec<(Layout, f32)>,
+    criteria: &[Criterion],
+) -> Vec<(Layout, Vec<f32>)> {
+    let stats: Vec<Vec<f32>> = results
+        .iter()
+        .map(|(l, _)| analyzer.calc_stats(l))
+        .collect();
+    let front = pareto_fronts(&stats, criteria)
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+    let mut results: Vec<Option<(Layout, Vec<f32>)>> = results
+        .into_iter()
+        .zip(stats)
+        .map(|((l, _), s)| Some((l, s)))
+        .collect();
+    front
+        .into_iter()
+        .filter_map(|i| results[i].take())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{opt::tests::setup_analyzer, Pos, Swap};
+    #[test]
+    fn test_rank() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let mirror: Vec<Pos> = (0..30).map(|p| (9 - p / 3) * 3 + p % 3).collect();
+        let mirror = Permutation::from_mapping(&mirror);
+        let mut mirrored = qwerty.clone();
+        mirrored.permute(&mirror);
+        let mut swapped = qwerty.clone();
+        swapped.swap(&Swap::new(0, 1));
+
+        let results = vec![
+            (qwerty.clone(), 1.0),
+            (mirrored.clone(), 1.0),
+            (qwerty.clone(), 1.0),
+            (swapped.clone(), 2.0),
+        ];
+        assert_eq!(3, dedup(results.clone(), None).len());
+        let deduped = dedup(results, Some(&mirror));
+        assert_eq!(2, deduped.len());
+        assert_eq!(qwerty.0, deduped[0].0 .0);
+        assert_eq!(swapped.0, deduped[1].0 .0);
+
+        let criteria = [Criterion::minimize(0), Criterion::maximize(1)];
+        let stats = vec![
+            vec![1.0, 1.0],
+            vec![2.0, 2.0],
+            vec![2.0, 1.0],
+            vec![3.0, 0.0],
+            vec![1.0, 1.0],
+        ];
+        assert!(dominates(&criteria, &stats[0], &stats[2]));
+        assert!(!dominates(&criteria, &stats[0], &stats[1]));
+        assert!(!dominates(&criteria, &stats[0], &stats[4]));
+        assert_eq!(
+            vec![vec![0, 1, 4], vec![2], vec![3]],
+            pareto_fronts(&stats, &criteria)
+        );
+        let stats = vec![vec![3.0], vec![0.0], vec![4.0], vec![1.0]];
+        assert_eq!(
+            vec![0.75, f32::INFINITY, f32::INFINITY, 0.75],
+            crowding_distances(&stats, &[0, 1, 2, 3], &[Criterion::minimize(0)])
+        );
+
+        // "qu" becomes a same hand bigram
+        let mut worse = qwerty.clone();
+        worse.swap(&Swap::new(0, 29));
+        let front = pareto_front(
+            &analyzer,
+            vec![(worse, 0.0), (qwerty.clone(), 0.0)],
+            &[Criterion::minimize(0)],
+        );
+        assert_eq!(1, front.len());
+        assert_eq!(qwerty.0, front[0].0 .0);
+        assert_eq!(analyzer.calc_stats(&qwerty), front[0].1);
+    }
+}
@@ -0,0 +1,243 @@
+use super::{
+    by_score, check_layouts, check_results, check_scores, possible_swaps, Objective, Optimizer,
+    Progress, RunControl, SetupError,
+};
+use crate::constraint::{self, Constraint};
+use crate::{analysis::Analyzer, state::LayoutState, Error, Layout};
+use rayon::prelude::*;
+
+/// An `Optimizer` running tabu search. Every iteration, it evaluates
+/// every swap and applies the best one, even if it makes the layout
+/// worse. Swapping the same positions again is tabu for `tenure`
+/// iterations, which keeps the search from going back to where it came
+/// from, unless doing so would give the best score seen so far
+/// (aspiration).
+///
+/// Each layout is left at the best state found during its search.
+pub struct TabuOptimizer {
+    layouts: Vec<Layout>,
+    pins: Vec<usize>,
+    constraints: Vec<Constraint>,
+    control: RunControl,
+    history: Vec<Vec<f32>>,
+    /// The number of swaps to make before finishing.
+    pub iterations: u64,
+    /// The number of iterations a swap stays tabu after being applied.
+    pub tenure: u64,
+}
+
+impl TabuOptimizer {
+    #[must_use]
+    pub fn new(iterations: u64) -> Self {
+        Self {
+            layouts: vec![],
+            pins: vec![],
+            constraints: vec![],
+            control: RunControl::default(),
+            history: vec![],
+            iterations,
+            tenure: 10,
+        }
+    }
+    #[must_use]
+    pub fn with_tenure(mut self, tenure: u64) -> Self {
+        self.tenure = tenure;
+        self
+    }
+    /// Like `Optimizer::setup`, but optimizes several layouts, such as
+    /// the ones returned by another optimizer.
+    pub fn setup_many(
+        &mut self,
+        layouts: impl IntoIterator<Item = Layout>,
+    ) -> Result<(), SetupError> {
+        self.layouts = check_layouts(layouts, &self.constraints)?;
+        Ok(())
+    }
+    /// The best score after each iteration of the last run, for each
+    /// layout in the order they were returned.
+    #[must_use]
+    pub fn history(&self) -> &[Vec<f32>] {
+        &self.history
+    }
+    /// Searches from the layout of population member `member`,
+    /// returning the best score after each iteration.
+    fn search(
+        &self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+        member: usize,
+        state: &mut LayoutState,
+    ) -> Vec<f32> {
+        let possible_swaps = possible_swaps(state.layout().0.len(), &self.pins);
+        // the iteration until which each swap of `possible_swaps` is tabu
+        let mut tabu_until = vec![0; possible_swaps.len()];
+
+        let start = objective.score(state.stats());
+        let mut history = vec![];
+        let mut diffs = vec![0.0; state.stats().len()];
+        let mut best_diffs = diffs.clone();
+        // scores relative to the starting layout
+        let mut score = 0.0;
+        let mut best = 0.0;
+        let mut best_layout = state.layout().clone();
+        for i in 0..self.iterations {
+            if self.control.should_stop(i) {
+                break;
+            }
+            let mut chosen: Option<(usize, f32)> = None;
+            for (s, swap) in possible_swaps.iter().enumerate() {
+                if !constraint::allows_swap(&self.constraints, state.layout(), swap) {
+                    continue;
+                }
+                diffs.copy_from_slice(state.swap_diff(analyzer, swap));
+                let diff = objective.score_transition(state.stats(), &diffs);
+                let aspirated = score + diff < best;
+                if (tabu_until[s] <= i || aspirated) && chosen.map_or(true, |(_, d)| diff < d) {
+                    chosen = Some((s, diff));
+                    best_diffs.copy_from_slice(&diffs);
+                }
+            }
+            let Some((s, diff)) = chosen else {
+                break;
+            };
+            state.apply(analyzer, &possible_swaps[s], &best_diffs);
+            state.commit();
+            tabu_until[s] = i + 1 + self.tenure;
+            score += diff;
+            if score < best {
+                best = score;
+                best_layout = state.layout().clone();
+            }
+            history.push(start + best);
+            if self.control.should_report(i + 1) {
+                self.control.report(&Progress {
+                    member,
+                    iteration: i + 1,
+                    best_score: start + best,
+                    best_layout: &best_layout,
+                });
+            }
+        }
+        if best < score {
+            *state = LayoutState::new(analyzer, best_layout);
+        }
+        history
+    }
+}
+
+impl Optimizer for TabuOptimizer {
+    fn setup(&mut self, l: Layout) -> Result<(), SetupError> {
+        self.setup_many([l])
+    }
+
+    fn pin(mut self, pins: Vec<usize>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    fn constrain(mut self, constraints: Vec<Constraint>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    fn control(mut self, control: RunControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    fn run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Vec<(Layout, f32)> {
+        let mut population = std::mem::take(&mut self.layouts);
+        let mut results: Vec<(Layout, f32, Vec<f32>)> = population
+            .par_iter_mut()
+            .enumerate()
+            .map(|(member, l)| {
+                let mut state = LayoutState::new(analyzer, l.clone());
+                let history = self.search(analyzer, objective, member, &mut state);
+                let score = objective.score(state.stats());
+                *l = state.into_layout();
+                (l.clone(), score, history)
+            })
+            .collect();
+        self.layouts = population;
+        results.sort_by(|a, b| by_score(a.1, b.1));
+        let (layouts, history) = results
+            .into_iter()
+            .map(|(l, score, history)| ((l, score), history))
+            .unzip();
+        self.history = history;
+        layouts
+    }
+
+    fn try_run(
+        &mut self,
+        analyzer: &Analyzer,
+        objective: &(dyn Objective + Send + Sync),
+    ) -> Result<Vec<(Layout, f32)>, Error> {
+        check_scores(analyzer, objective, &self.layouts)?;
+        check_results(self.run(analyzer, objective))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{MetricAmount, MetricData, NstrokeData};
+    use crate::opt::{tests::setup_analyzer, GreedyOptimizer, Strategy, Weight, WeightsObjective};
+    use crate::{NgramType, Nstroke};
+    #[test]
+    fn test_tabu() {
+        let analyzer = setup_analyzer();
+        let qwerty = analyzer
+            .corpus
+            .layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+        let objective = WeightsObjective::new(vec![Weight {
+            metric: 0,
+            weight: 1.0,
+        }]);
+
+        let mut greedy = GreedyOptimizer::new(Strategy::Steepest).pin(vec![0]);
+        greedy.setup(qwerty.clone()).expect("no constraints");
+        let (local_optimum, _) = greedy.run(&analyzer, &objective).remove(0);
+
+        let mut optimizer = TabuOptimizer::new(100).pin(vec![0]);
+        optimizer
+            .setup_many([qwerty.clone(), local_optimum])
+            .expect("no constraints");
+        let optimized = optimizer.run(&analyzer, &objective);
+        assert_eq!(2, optimizer.history().len());
+        for ((layout, score), history) in optimized.iter().zip(optimizer.history()) {
+            assert_eq!(qwerty.0[0], layout.0[0]);
+            assert_eq!(100, history.len());
+            assert!(history.windows(2).all(|w| w[1] <= w[0]));
+            assert!((history[99] - score).abs() < 1e-3);
+        }
+
+        // bigrams cost the distance between their positions, which has
+        // local optima that no single swap improves on
+        let mut strokes = vec![];
+        for a in 0..30_usize {
+            for b in (0..30).filter(|b| *b != a) {
+                strokes.push(NstrokeData::new(
+                    Nstroke::Bistroke([a, b]),
+                    vec![MetricAmount::new(0, a.abs_diff(b) as f32)],
+                ));
+            }
+        }
+        let data = MetricData::from(vec![NgramType::Bigram], strokes, 30);
+        let analyzer = Analyzer::from(data, analyzer.corpus);
+        let mut greedy = GreedyOptimizer::new(Strategy::Steepest).pin(vec![0]);
+        greedy.setup(qwerty).expect("no constraints");
+        let (local_optimum, greedy_score) = greedy.run(&analyzer, &objective).remove(0);
+        let mut escape = TabuOptimizer::new(20).pin(vec![0]);
+        escape.setup(local_optimum).expect("no constraints");
+        let (_, escaped) = escape.run(&analyzer, &objective).remove(0);
+        assert!(
+            escaped < greedy_score,
+            "tabu search should escape the local optimum {greedy_score}, got {escaped}"
+        );
+    }
+}
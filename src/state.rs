@@ -0,0 +1,250 @@
+use crate::analysis::{Analyzer, StrokeCache, SwapMatrix};
+use crate::{Layout, Permutation, Swap};
+
+/// A change applied to a `LayoutState`.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Swap(Swap),
+    Permutation(Permutation),
+}
+
+/// A `Layout` paired with its current stats. Swaps applied through
+/// the state update the stats incrementally, and can be undone.
+///
+/// The state also caches the current frequency of every stroke, which
+/// makes `LayoutState::swap_diff` faster than `Analyzer::swap_diff`.
+///
+/// Use `LayoutState::is_consistent` to check the stats against a full
+/// recalculation. Debug builds check every `CHECK_INTERVAL`th update
+/// this way, and the crate's own tests check every update.
+#[derive(Clone)]
+pub struct LayoutState {
+    layout: Layout,
+    stats: Vec<f32>,
+    cache: StrokeCache,
+    diffs: Vec<f32>,
+    history: Vec<Change>,
+    /// The diffs of each change in `history`, stored contiguously.
+    history_diffs: Vec<f32>,
+    /// The number of updates since the stats were last checked.
+    #[cfg(debug_assertions)]
+    unchecked: usize,
+}
+
+/// How many updates a debug build applies between checks of the stats
+/// against a full recalculation, which is as slow as `Analyzer::calc_stats`.
+#[cfg(debug_assertions)]
+const CHECK_INTERVAL: usize = if cfg!(test) { 1 } else { 256 };
+
+impl LayoutState {
+    #[must_use]
+    pub fn new(analyzer: &Analyzer, layout: Layout) -> Self {
+        let stats = analyzer.calc_stats(&layout);
+        Self {
+            cache: analyzer.stroke_cache(&layout),
+            diffs: vec![0.0; stats.len()],
+            layout,
+            stats,
+            history: vec![],
+            history_diffs: vec![],
+            #[cfg(debug_assertions)]
+            unchecked: 0,
+        }
+    }
+    #[must_use]
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+    #[must_use]
+    pub fn stats(&self) -> &[f32] {
+        &self.stats
+    }
+    #[must_use]
+    pub fn into_layout(self) -> Layout {
+        self.layout
+    }
+    /// Calculates the diff of `swap` without applying it, including
+    /// the aggregate metrics. The returned slice is only valid until
+    /// the state is next used.
+    pub fn swap_diff(&mut self, analyzer: &Analyzer, swap: &Swap) -> &[f32] {
+        self.diffs.fill(0.0);
+        analyzer.cached_swap_diff(&mut self.diffs, &self.layout, swap, &self.cache);
+        analyzer.aggregate_diffs(&self.stats, &mut self.diffs);
+        &self.diffs
+    }
+    /// Calculates the diffs of every possible swap at once.
+    #[must_use]
+    pub fn swap_matrix(&self, analyzer: &Analyzer) -> SwapMatrix {
+        analyzer.cached_swap_matrix(&self.layout, &self.stats, &self.cache)
+    }
+    /// Applies `swap`, updating the stats.
+    pub fn swap(&mut self, analyzer: &Analyzer, swap: &Swap) {
+        let mut diffs = std::mem::take(&mut self.diffs);
+        diffs.fill(0.0);
+        analyzer.cached_swap_diff(&mut diffs, &self.layout, swap, &self.cache);
+        analyzer.aggregate_diffs(&self.stats, &mut diffs);
+        self.apply(analyzer, swap, &diffs);
+        self.diffs = diffs;
+    }
+    /// Applies `swap` using diffs that were already calculated for
+    /// it, such as the ones returned by `LayoutState::swap_diff`.
+    pub fn apply(&mut self, analyzer: &Analyzer, swap: &Swap, diffs: &[f32]) {
+        for (stat, diff) in self.stats.iter_mut().zip(diffs) {
+            *stat += diff;
+        }
+        analyzer.update_aggregates(&mut self.stats);
+        self.layout.swap(swap);
+        analyzer.update_stroke_cache(&mut self.cache, &self.layout, [swap.a, swap.b]);
+        self.history.push(Change::Swap(swap.clone()));
+        self.history_diffs.extend_from_slice(diffs);
+        self.debug_check(analyzer);
+    }
+    /// Applies a `Permutation`, updating the stats.
+    pub fn permute(&mut self, analyzer: &Analyzer, p: &Permutation) {
+        self.diffs.fill(0.0);
+        analyzer.permutation_diff(&mut self.diffs, &self.layout, p);
+        analyzer.aggregate_diffs(&self.stats, &mut self.diffs);
+        for (stat, diff) in self.stats.iter_mut().zip(&self.diffs) {
+            *stat += diff;
+        }
+        analyzer.update_aggregates(&mut self.stats);
+        self.layout.permute(p);
+        analyzer.update_stroke_cache(&mut self.cache, &self.layout, p.positions());
+        self.history.push(Change::Permutation(p.clone()));
+        self.history_diffs.extend_from_slice(&self.diffs);
+        self.debug_check(analyzer);
+    }
+    /// Reverts the last applied change, returning it. Returns `None`
+    /// if there is nothing to undo.
+    pub fn undo(&mut self, analyzer: &Analyzer) -> Option<Change> {
+        let change = self.history.pop()?;
+        let start = self.history_diffs.len() - self.stats.len();
+        for (stat, diff) in self.stats.iter_mut().zip(&self.history_diffs[start..]) {
+            *stat -= diff;
+        }
+        analyzer.update_aggregates(&mut self.stats);
+        self.history_diffs.truncate(start);
+        match &change {
+            Change::Swap(swap) => {
+                self.layout.swap(swap);
+                analyzer.update_stroke_cache(&mut self.cache, &self.layout, [swap.a, swap.b]);
+            }
+            Change::Permutation(p) => {
+                self.layout.permute(&p.inverse());
+                analyzer.update_stroke_cache(&mut self.cache, &self.layout, p.positions());
+            }
+        }
+        self.debug_check(analyzer);
+        Some(change)
+    }
+    /// Forgets the undo history, making the current state permanent.
+    pub fn commit(&mut self) {
+        self.history.clear();
+        self.history_diffs.clear();
+    }
+    /// Recalculates the stats from scratch, discarding any error
+    /// accumulated by incremental updates.
+    pub fn resync(&mut self, analyzer: &Analyzer) {
+        self.stats.fill(0.0);
+        analyzer.recalc_stats(&mut self.stats, &self.layout);
+        self.cache = analyzer.stroke_cache(&self.layout);
+    }
+    /// Returns whether the incrementally updated stats agree with a
+    /// full recalculation, within floating point error.
+    #[must_use]
+    pub fn is_consistent(&self, analyzer: &Analyzer) -> bool {
+        let expected = analyzer.calc_stats(&self.layout);
+        expected
+            .iter()
+            .zip(&self.stats)
+            .all(|(e, s)| (e - s).abs() <= 1e-3 * e.abs().max(1.0))
+    }
+    #[cfg(debug_assertions)]
+    fn debug_check(&mut self, analyzer: &Analyzer) {
+        self.unchecked += 1;
+        if self.unchecked < CHECK_INTERVAL {
+            return;
+        }
+        self.unchecked = 0;
+        debug_assert!(
+            self.is_consistent(analyzer),
+            "incremental stats {:?} diverged from recalculated stats {:?}",
+            self.stats,
+            analyzer.calc_stats(&self.layout)
+        );
+    }
+    #[cfg(not(debug_assertions))]
+    #[allow(clippy::unused_self)]
+    fn debug_check(&mut self, _analyzer: &Analyzer) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{AggregateMetric, Aggregation, MetricAmount, MetricData, NstrokeData};
+    use crate::{Corpus, NgramType, Nstroke};
+    #[test]
+    fn test_layout_state() {
+        let mut corpus = Corpus::with_char_list(
+            "abcdefghijklmnopqrstuvwxyz,./;"
+                .chars()
+                .map(|c| vec![c])
+                .collect(),
+        );
+        corpus.add_str("the quick brown fox jumps over the lazy dog");
+        let qwerty = corpus.layout_from_str("qazwsxedcrfvtgbyhnujmik,lo.p;/");
+
+        let metrics = vec![NgramType::Bigram, NgramType::Skipgram, NgramType::Trigram];
+        let mut strokes = vec![];
+        for a in 0..30 {
+            for b in 0..30 {
+                if a / 3 == b / 3 {
+                    strokes.push(NstrokeData::new(
+                        Nstroke::Bistroke([a, b]),
+                        vec![MetricAmount::new(0, 1.0), MetricAmount::new(1, 0.5)],
+                    ));
+                }
+            }
+            strokes.push(NstrokeData::new(
+                Nstroke::Tristroke([a, (a + 1) % 30, (a + 2) % 30]),
+                vec![MetricAmount::new(2, 2.0)],
+            ));
+        }
+        let data =
+            MetricData::from(metrics, strokes, 30).with_aggregates(vec![AggregateMetric::new(
+                Aggregation::Variance,
+                vec![0, 1, 2],
+            )
+            .expect("inputs")]);
+        let analyzer = Analyzer::from(data, corpus);
+
+        let mut state = LayoutState::new(&analyzer, qwerty.clone());
+        let original = state.stats().to_vec();
+        let swaps = [Swap::new(12, 6), Swap::new(13, 16), Swap::new(3, 4)];
+        for swap in &swaps {
+            let mut expected = vec![0.0; original.len()];
+            analyzer.swap_diff(&mut expected, state.layout(), swap);
+            analyzer.aggregate_diffs(state.stats(), &mut expected);
+            assert_eq!(expected, state.swap_diff(&analyzer, swap));
+            state.swap(&analyzer, swap);
+            assert!(state.is_consistent(&analyzer));
+        }
+        assert_ne!(original, state.stats());
+        state.permute(&analyzer, &Permutation::cycle(&[0, 15, 29, 7]));
+        assert!(state.is_consistent(&analyzer));
+
+        assert!(matches!(
+            state.undo(&analyzer),
+            Some(Change::Permutation(_))
+        ));
+        for swap in swaps.iter().rev() {
+            let Some(Change::Swap(undone)) = state.undo(&analyzer) else {
+                panic!("swap should be undoable");
+            };
+            assert_eq!((swap.a, swap.b), (undone.a, undone.b));
+        }
+        assert!(state.undo(&analyzer).is_none());
+        assert_eq!(qwerty.0, state.layout().0);
+        assert!(state.is_consistent(&analyzer));
+    }
+}